@@ -0,0 +1,856 @@
+//! An `etcdctl`-style command line client built on `EtcdSession`, e.g.,
+//!
+//! ```text
+//! etcd3rs get --prefix /config/
+//! etcd3rs -w json put /config/mode fast --prev-kv
+//! etcd3rs --endpoint http://10.0.0.1:2379 endpoint status -w table
+//! ```
+//!
+//! The binary is built with `cargo build --features cli`.
+
+use clap::{Parser, Subcommand, ValueEnum};
+use etcdv3_rs::etcd_actions::EtcdSession;
+use etcdv3_rs::etcd_error::Result;
+use etcdv3_rs::etcd_export::{Export, Import, ImportMode};
+use etcdv3_rs::etcd_proto::*;
+use etcdv3_rs::etcd_range::RangeOptions;
+use futures::TryStreamExt;
+use serde::Serialize;
+use std::cmp;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+
+#[derive(Parser)]
+#[command(
+    name = "etcd3rs",
+    version,
+    about = "A command line client for the etcd v3 JSON gateway"
+)]
+struct Cli {
+    /// Address of the gateway.
+    #[arg(long, global = true, default_value = "http://localhost:2379")]
+    endpoint: String,
+
+    /// Output format.
+    #[arg(short = 'w', long, global = true, value_enum, default_value_t = Format::Simple)]
+    write_out: Format,
+
+    /// Authenticate as `name:password`.
+    #[arg(long, global = true)]
+    user: Option<String>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    Simple,
+    Json,
+    Table,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Read a key, or every key starting with it.
+    Get {
+        key: String,
+        #[arg(long)]
+        prefix: bool,
+        /// Read the store as it was at this revision.
+        #[arg(long)]
+        rev: Option<i64>,
+        /// Print keys without their values.
+        #[arg(long)]
+        keys_only: bool,
+        /// Return at most this many keys.
+        #[arg(long)]
+        limit: Option<usize>,
+    },
+    /// Write a key.
+    Put {
+        key: String,
+        value: String,
+        /// Attach the key to this lease, given in hexadecimal.
+        #[arg(long, value_parser = parse_lease)]
+        lease: Option<i64>,
+        /// Print the value the key had before.
+        #[arg(long)]
+        prev_kv: bool,
+    },
+    /// Delete a key, or every key starting with it.
+    Del {
+        key: String,
+        #[arg(long)]
+        prefix: bool,
+    },
+    /// Print changes to a key, or every key starting with it, until interrupted.
+    Watch {
+        key: String,
+        #[arg(long)]
+        prefix: bool,
+        /// Start at this revision, replaying the changes made since.
+        #[arg(long)]
+        rev: Option<i64>,
+    },
+    /// Run the transaction described in a file, or on stdin for `-`. The file uses the format of
+    /// `etcdctl txn`: compares, a blank line, the success operations, a blank line and the failure
+    /// operations.
+    Txn { file: PathBuf },
+    /// Dump every key starting with a prefix as JSON Lines, read at a single revision.
+    Export {
+        prefix: String,
+        /// Read the keys as of this revision rather than the current one.
+        #[arg(long)]
+        rev: Option<i64>,
+        /// Number of keys fetched per request.
+        #[arg(long, default_value_t = 1000)]
+        page_size: usize,
+        /// Write the dump to this file rather than stdout.
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Load a dump written by `export` from a file, or from stdin for `-`.
+    Import {
+        file: PathBuf,
+        /// Count what would be imported without writing anything.
+        #[arg(long)]
+        dry_run: bool,
+        /// Replace this prefix of every key with `--to-prefix`.
+        #[arg(long, requires = "to_prefix")]
+        from_prefix: Option<String>,
+        #[arg(long, requires = "from_prefix")]
+        to_prefix: Option<String>,
+        /// Keys written per transaction.
+        #[arg(long, default_value_t = 64)]
+        batch_size: usize,
+        /// Leave keys that already exist untouched rather than overwriting them.
+        #[arg(long)]
+        put_if_absent: bool,
+        /// Attach keys to the lease they were exported with, which must exist.
+        #[arg(long)]
+        keep_leases: bool,
+    },
+    /// Grant, revoke and refresh leases.
+    Lease {
+        #[command(subcommand)]
+        command: LeaseCommand,
+    },
+    /// Inspect the members of the cluster.
+    Member {
+        #[command(subcommand)]
+        command: MemberCommand,
+    },
+    /// Inspect the member behind `--endpoint`.
+    Endpoint {
+        #[command(subcommand)]
+        command: EndpointCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum LeaseCommand {
+    /// Grant a lease with a TTL in seconds.
+    Grant { ttl: i64 },
+    /// Revoke a lease, deleting every key attached to it.
+    Revoke {
+        #[arg(value_parser = parse_lease)]
+        id: i64,
+    },
+    /// Keep a lease alive until interrupted.
+    Keepalive {
+        #[arg(value_parser = parse_lease)]
+        id: i64,
+        /// Refresh the lease once and exit.
+        #[arg(long)]
+        once: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum MemberCommand {
+    List,
+}
+
+#[derive(Subcommand)]
+enum EndpointCommand {
+    Status,
+}
+
+/// Lease IDs are written in hexadecimal, as `etcdctl` does.
+fn parse_lease(id: &str) -> ::std::result::Result<i64, String> {
+    u64::from_str_radix(id, 16)
+        .map(|id| id as i64)
+        .map_err(|err| err.to_string())
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let result = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(From::from)
+        .and_then(|runtime| runtime.block_on(run(cli)));
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(cli: Cli) -> Result<()> {
+    let session = EtcdSession::new(&cli.endpoint);
+    if let Some(ref user) = cli.user {
+        let (name, password) = user.split_once(':').unwrap_or((user, ""));
+        session.authenticate(name, password).await?;
+    }
+    let format = cli.write_out;
+    match cli.command {
+        Command::Get {
+            key,
+            prefix,
+            rev,
+            keys_only,
+            limit,
+        } => {
+            let mut opts = if prefix {
+                RangeOptions::prefix(&key)
+            } else {
+                RangeOptions::key(&key)
+            };
+            if let Some(rev) = rev {
+                opts = opts.revision(rev);
+            }
+            if let Some(limit) = limit {
+                opts = opts.limit(limit);
+            }
+            if keys_only {
+                opts = opts.keys_only();
+            }
+            let resp = session.range_raw(opts.request()).await?;
+            let kvs = resp.kvs.as_deref().unwrap_or(&[]);
+            let mut report = if keys_only {
+                Report::new(&["KEY"])
+            } else {
+                Report::new(&["KEY", "VALUE"])
+            };
+            for kv in kvs {
+                let mut row = vec![key_of(kv)];
+                if !keys_only {
+                    row.push(value_of(kv));
+                }
+                report.lines.extend(row.iter().cloned());
+                report.rows.push(row);
+            }
+            report.print(format, &resp)
+        }
+        Command::Put {
+            key,
+            value,
+            lease,
+            prev_kv,
+        } => {
+            let req = PutRequest {
+                prev_kv: Some(true).filter(|_| prev_kv),
+                ..match lease {
+                    Some(lease) => PutRequest::new_with_lease(&key, &value, lease),
+                    None => PutRequest::new(&key, &value),
+                }
+            };
+            let resp = session.put_raw(&req).await?;
+            let mut report = Report::new(&["KEY", "REVISION", "PREV VALUE"]);
+            report.lines.push(String::from("OK"));
+            let previous = resp.prev_kv.as_ref().map(value_of).unwrap_or_default();
+            if let Some(ref kv) = resp.prev_kv {
+                report.lines.push(key_of(kv));
+                report.lines.push(previous.clone());
+            }
+            report
+                .rows
+                .push(vec![key, revision_of(&resp.header), previous]);
+            report.print(format, &resp)
+        }
+        Command::Del { key, prefix } => {
+            let req = if prefix {
+                DeleteRangeRequest::new_for_prefix(&key)
+            } else {
+                DeleteRangeRequest::new(&key)
+            };
+            let resp = session.delete_range_raw(&req).await?;
+            let mut report = Report::new(&["DELETED"]);
+            report.lines.push(resp.deleted().to_string());
+            report.rows.push(vec![resp.deleted().to_string()]);
+            report.print(format, &resp)
+        }
+        Command::Watch { key, prefix, rev } => {
+            let req = WatchCreateRequest {
+                start_revision: rev.map(|rev| rev.to_string()),
+                ..if prefix {
+                    WatchCreateRequest::new_for_prefix(&key)
+                } else {
+                    WatchCreateRequest::new_for_key(&key)
+                }
+            };
+            let mut watch = session.watch_with(req).await?;
+            while let Some(resp) = watch.try_next().await? {
+                let events = resp.events.as_deref().unwrap_or(&[]);
+                // Only the JSON output shows responses without events, e.g., the creation notice.
+                if events.is_empty() && !matches!(format, Format::Json) {
+                    continue;
+                }
+                let mut report = Report::new(&["TYPE", "KEY", "VALUE", "REVISION"]);
+                for event in events {
+                    let kind = match event.event_type() {
+                        Some(EventType::DELETE) => "DELETE",
+                        _ => "PUT",
+                    };
+                    let kv = event.kv.clone().unwrap_or_default();
+                    report.lines.push(String::from(kind));
+                    report.lines.push(key_of(&kv));
+                    if kind == "PUT" {
+                        report.lines.push(value_of(&kv));
+                    }
+                    report.rows.push(vec![
+                        String::from(kind),
+                        key_of(&kv),
+                        value_of(&kv),
+                        kv.mod_rev().to_string(),
+                    ]);
+                }
+                report.print(format, &resp)?;
+            }
+            Ok(())
+        }
+        Command::Txn { file } => {
+            let mut text = String::new();
+            if file.as_os_str() == "-" {
+                io::stdin().read_to_string(&mut text)?;
+            } else {
+                text = fs::read_to_string(&file)?;
+            }
+            let resp = session.txn(&parse_txn(&text)?).await?;
+            let mut report = Report::new(&["SUCCEEDED", "RESPONSES"]);
+            txn_lines(&resp, &mut report.lines);
+            let responses = resp.responses.as_ref().map_or(0, Vec::len);
+            report
+                .rows
+                .push(vec![resp.succeeded().to_string(), responses.to_string()]);
+            report.print(format, &resp)
+        }
+        Command::Export {
+            prefix,
+            rev,
+            page_size,
+            output,
+        } => {
+            let mut export = Export::new(&session, &prefix).page_size(page_size);
+            if let Some(rev) = rev {
+                export = export.revision(rev);
+            }
+            let summary = match output {
+                Some(path) => export.write_to(BufWriter::new(File::create(path)?)).await?,
+                None => export.write_to(io::stdout().lock()).await?,
+            };
+            // The dump may be going to stdout, so the summary goes to stderr.
+            eprintln!(
+                "exported {} keys at revision {}",
+                summary.keys, summary.revision
+            );
+            Ok(())
+        }
+        Command::Import {
+            file,
+            dry_run,
+            from_prefix,
+            to_prefix,
+            batch_size,
+            put_if_absent,
+            keep_leases,
+        } => {
+            let mut import = Import::new(&session)
+                .dry_run(dry_run)
+                .batch_size(batch_size)
+                .keep_leases(keep_leases);
+            if put_if_absent {
+                import = import.mode(ImportMode::PutIfAbsent);
+            }
+            if let (Some(from), Some(to)) = (from_prefix, to_prefix) {
+                import = import.rewrite_prefix(&from, &to);
+            }
+            let summary = if file.as_os_str() == "-" {
+                import.read_from(io::stdin().lock()).await?
+            } else {
+                import.read_from(BufReader::new(File::open(file)?)).await?
+            };
+            let mut report = Report::new(&["IMPORTED", "SKIPPED", "BATCHES"]);
+            report.lines.push(format!(
+                "{}imported {} keys, skipped {} in {} batches",
+                if dry_run { "dry run: " } else { "" },
+                summary.imported,
+                summary.skipped,
+                summary.batches
+            ));
+            report.rows.push(vec![
+                summary.imported.to_string(),
+                summary.skipped.to_string(),
+                summary.batches.to_string(),
+            ]);
+            report.print(format, &summary)
+        }
+        Command::Lease { command } => lease(&session, format, command).await,
+        Command::Member {
+            command: MemberCommand::List,
+        } => {
+            let resp = session.member_list().await?;
+            let mut report = Report::new(&[
+                "ID",
+                "STATUS",
+                "NAME",
+                "PEER ADDRS",
+                "CLIENT ADDRS",
+                "IS LEARNER",
+            ]);
+            for member in resp.members.as_deref().unwrap_or(&[]) {
+                let name = member.name.clone().unwrap_or_default();
+                let row = vec![
+                    format!("{:x}", member.id()),
+                    String::from(if name.is_empty() {
+                        "unstarted"
+                    } else {
+                        "started"
+                    }),
+                    name,
+                    member.peer_urls.clone().unwrap_or_default().join(","),
+                    member.client_urls.clone().unwrap_or_default().join(","),
+                    member.is_learner.unwrap_or(false).to_string(),
+                ];
+                report.lines.push(row.join(", "));
+                report.rows.push(row);
+            }
+            report.print(format, &resp)
+        }
+        Command::Endpoint {
+            command: EndpointCommand::Status,
+        } => {
+            let resp = session.status().await?;
+            let mut report = Report::new(&[
+                "ENDPOINT",
+                "ID",
+                "VERSION",
+                "DB SIZE",
+                "IS LEADER",
+                "RAFT TERM",
+                "RAFT INDEX",
+                "ERRORS",
+            ]);
+            let row = vec![
+                cli.endpoint.clone(),
+                format!("{:x}", resp.member_id()),
+                resp.version.clone().unwrap_or_default(),
+                format!("{} B", resp.db_size()),
+                (resp.member_id() == resp.leader()).to_string(),
+                resp.raft_term().to_string(),
+                resp.raft_index().to_string(),
+                resp.errors.clone().unwrap_or_default().join(", "),
+            ];
+            report.lines.push(row.join(", "));
+            report.rows.push(row);
+            report.print(format, &resp)
+        }
+    }
+}
+
+async fn lease(session: &EtcdSession, format: Format, command: LeaseCommand) -> Result<()> {
+    match command {
+        LeaseCommand::Grant { ttl } => {
+            let resp = session.lease_grant(ttl).await?;
+            if let Some(ref err) = resp.error {
+                return Err(invalid(err));
+            }
+            let id = resp.id().unwrap_or(0);
+            let ttl = resp.ttl().unwrap_or(0);
+            let mut report = Report::new(&["ID", "TTL"]);
+            report
+                .lines
+                .push(format!("lease {:x} granted with TTL({}s)", id, ttl));
+            report.rows.push(vec![format!("{:x}", id), ttl.to_string()]);
+            report.print(format, &resp)
+        }
+        LeaseCommand::Revoke { id } => {
+            session.lease_revoke(id).await?;
+            let mut report = Report::new(&["ID"]);
+            report.lines.push(format!("lease {:x} revoked", id));
+            report.rows.push(vec![format!("{:x}", id)]);
+            report.print(format, &LeaseRevokeRequest::new(id))
+        }
+        LeaseCommand::Keepalive { id, once } => loop {
+            let resp = session.lease_keep_alive(id).await?;
+            let ttl = resp.ttl();
+            if ttl == 0 {
+                return Err(invalid(&format!("lease {:x} expired or revoked", id)));
+            }
+            let mut report = Report::new(&["ID", "TTL"]);
+            report
+                .lines
+                .push(format!("lease {:x} keepalived with TTL({})", id, ttl));
+            report.rows.push(vec![format!("{:x}", id), ttl.to_string()]);
+            report.print(format, &resp)?;
+            if once {
+                return Ok(());
+            }
+            tokio::time::sleep(Duration::from_secs(cmp::max(ttl / 3, 1) as u64)).await;
+        },
+    }
+}
+
+/// What a command prints: `lines` in the simple format, and `header` and `rows` as a table. The
+/// JSON format prints the response as the gateway returned it.
+struct Report {
+    lines: Vec<String>,
+    header: &'static [&'static str],
+    rows: Vec<Vec<String>>,
+}
+
+impl Report {
+    fn new(header: &'static [&'static str]) -> Report {
+        Report {
+            lines: Vec::new(),
+            header,
+            rows: Vec::new(),
+        }
+    }
+
+    fn print<T: Serialize>(&self, format: Format, resp: &T) -> Result<()> {
+        match format {
+            Format::Simple => {
+                for line in &self.lines {
+                    println!("{}", line);
+                }
+            }
+            Format::Json => println!("{}", serde_json::to_string(resp)?),
+            Format::Table => print!("{}", self.table()),
+        }
+        Ok(())
+    }
+
+    fn table(&self) -> String {
+        let mut widths: Vec<usize> = self.header.iter().map(|h| h.len()).collect();
+        for row in &self.rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = cmp::max(*width, cell.chars().count());
+            }
+        }
+        let rule: String = widths
+            .iter()
+            .map(|width| format!("+{}", "-".repeat(width + 2)))
+            .collect::<String>()
+            + "+\n";
+        let line = |cells: &[&str]| {
+            cells
+                .iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("| {:<width$} ", cell, width = width))
+                .collect::<String>()
+                + "|\n"
+        };
+        let mut table = rule.clone();
+        table += &line(self.header);
+        table += &rule;
+        for row in &self.rows {
+            let cells: Vec<&str> = row.iter().map(String::as_str).collect();
+            table += &line(&cells);
+        }
+        table + &rule
+    }
+}
+
+fn key_of(kv: &KeyValue) -> String {
+    String::from_utf8_lossy(&kv.key_as_u8().unwrap_or_default()).into_owned()
+}
+
+fn value_of(kv: &KeyValue) -> String {
+    String::from_utf8_lossy(&kv.value_as_u8().unwrap_or_default()).into_owned()
+}
+
+fn revision_of(header: &Option<ResponseHeader>) -> String {
+    header.as_ref().map_or(0, ResponseHeader::rev).to_string()
+}
+
+fn invalid(msg: &str) -> etcdv3_rs::etcd_error::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, String::from(msg)).into()
+}
+
+/// The simple output of a transaction, which is how `etcdctl txn` prints it.
+fn txn_lines(resp: &TxnResponse, lines: &mut Vec<String>) {
+    lines.push(String::from(if resp.succeeded() {
+        "SUCCESS"
+    } else {
+        "FAILURE"
+    }));
+    for op in resp.responses.as_deref().unwrap_or(&[]) {
+        lines.push(String::new());
+        match *op {
+            ResponseOp::Put(_) => lines.push(String::from("OK")),
+            ResponseOp::Range(ref range) => {
+                for kv in range.kvs.as_deref().unwrap_or(&[]) {
+                    lines.push(key_of(kv));
+                    lines.push(value_of(kv));
+                }
+            }
+            ResponseOp::DeleteRange(ref delete) => lines.push(delete.deleted().to_string()),
+            ResponseOp::Txn(ref txn) => txn_lines(txn, lines),
+        }
+    }
+}
+
+/// Parse a transaction in the format of `etcdctl txn`, e.g.,
+///
+/// ```text
+/// mod("counter") = "4"
+///
+/// put counter 5
+///
+/// get counter
+/// ```
+///
+/// Compares check `value`, `version`, `create`, `mod` or `lease` of a key with `=`, `!=`, `<` or
+/// `>`. Operations are `put <key> <value>`, `get <key>` and `del <key>`, where `get` and `del`
+/// accept `--prefix`.
+fn parse_txn(text: &str) -> Result<TxnRequest> {
+    let mut sections = vec![Vec::new()];
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() {
+            sections.push(Vec::new());
+        } else {
+            sections.last_mut().unwrap().push(line);
+        }
+    }
+    if sections.len() > 3 && sections[3..].iter().any(|section| !section.is_empty()) {
+        return Err(invalid("a transaction has at most three sections"));
+    }
+    sections.resize(3, Vec::new());
+    let compare = sections[0]
+        .iter()
+        .map(|line| parse_compare(line))
+        .collect::<Result<_>>()?;
+    let success = sections[1]
+        .iter()
+        .map(|line| parse_op(line))
+        .collect::<Result<_>>()?;
+    let failure = sections[2]
+        .iter()
+        .map(|line| parse_op(line))
+        .collect::<Result<_>>()?;
+    Ok(TxnRequest::new(compare, success, failure))
+}
+
+fn parse_compare(line: &str) -> Result<Compare> {
+    let malformed = || invalid(&format!("malformed compare: {}", line));
+    let open = line.find('(').ok_or_else(malformed)?;
+    let tokens = tokenize(&line[open + 1..])?;
+    let (key, op, value) = match tokens.as_slice() {
+        [key, close, op, value] if close == ")" => (key, op, value),
+        _ => return Err(malformed()),
+    };
+    let result = match op.as_str() {
+        "=" | "==" => CompareResult::EQUAL,
+        "!=" => CompareResult::NOT_EQUAL,
+        "<" => CompareResult::LESS,
+        ">" => CompareResult::GREATER,
+        _ => return Err(malformed()),
+    };
+    let number = || value.parse::<i64>().map_err(|_| malformed());
+    match line[..open].trim() {
+        "val" | "value" => Ok(Compare::new_value(key, result, value)),
+        "ver" | "version" => Ok(Compare::new_version(key, result, number()?)),
+        "c" | "create" => Ok(Compare::new_create_revision(key, result, number()?)),
+        "m" | "mod" => Ok(Compare::new_mod_revision(key, result, number()?)),
+        "lease" => Ok(Compare::new_lease(
+            key,
+            result,
+            parse_lease(value).map_err(|_| malformed())?,
+        )),
+        _ => Err(malformed()),
+    }
+}
+
+fn parse_op(line: &str) -> Result<RequestOp> {
+    let tokens = tokenize(line)?;
+    let words: Vec<&str> = tokens.iter().map(String::as_str).collect();
+    match words.as_slice() {
+        ["put", key, value] => Ok(RequestOp::Put(PutRequest::new(key, value))),
+        ["get", key] => Ok(RequestOp::Range(RangeRequest::new(key))),
+        ["get", key, "--prefix"] => Ok(RequestOp::Range(RangeRequest::new_for_prefix(key))),
+        ["del", key] => Ok(RequestOp::DeleteRange(DeleteRangeRequest::new(key))),
+        ["del", key, "--prefix"] => Ok(RequestOp::DeleteRange(DeleteRangeRequest::new_for_prefix(
+            key,
+        ))),
+        _ => Err(invalid(&format!("malformed operation: {}", line))),
+    }
+}
+
+/// Split a line into words, where double quoted strings (with `\` escapes) are single words and
+/// `)` is a word of its own.
+fn tokenize(line: &str) -> Result<Vec<String>> {
+    let mut tokens = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            ')' => tokens.push(String::from(")")),
+            '"' => {
+                let mut token = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => token.extend(chars.next()),
+                        Some(c) => token.push(c),
+                        None => return Err(invalid(&format!("unterminated quote: {}", line))),
+                    }
+                }
+                tokens.push(token);
+            }
+            c => {
+                let mut token = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == ')' || c == '"' {
+                        break;
+                    }
+                    token.push(c);
+                    chars.next();
+                }
+                tokens.push(token);
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn to_json<T: Serialize>(value: &T) -> Value {
+        serde_json::to_value(value).unwrap()
+    }
+
+    #[test]
+    fn tokenize_test() {
+        assert_eq!(
+            tokenize(r#"put "a key" plain"#).unwrap(),
+            vec!["put", "a key", "plain"]
+        );
+        assert_eq!(
+            tokenize(r#"value("k\"ey") != "4""#).unwrap(),
+            vec!["value(", "k\"ey", ")", "!=", "4"]
+        );
+        assert_eq!(tokenize(r#""""#).unwrap(), vec![""]);
+        assert!(tokenize(r#"put "open"#).is_err());
+    }
+
+    #[test]
+    fn parse_compare_test() {
+        assert_eq!(
+            to_json(&parse_compare(r#"mod("counter") = "4""#).unwrap()),
+            to_json(&Compare::new_mod_revision(
+                "counter",
+                CompareResult::EQUAL,
+                4
+            ))
+        );
+        assert_eq!(
+            to_json(&parse_compare(r#"value("k") != "v""#).unwrap()),
+            to_json(&Compare::new_value("k", CompareResult::NOT_EQUAL, "v"))
+        );
+        assert_eq!(
+            to_json(&parse_compare(r#"lease("k") > "1f""#).unwrap()),
+            to_json(&Compare::new_lease("k", CompareResult::GREATER, 0x1f))
+        );
+        assert!(parse_compare(r#"mod("k") = "four""#).is_err());
+        assert!(parse_compare(r#"size("k") = "1""#).is_err());
+        assert!(parse_compare(r#"mod("k" = "1""#).is_err());
+    }
+
+    #[test]
+    fn parse_op_test() {
+        assert_eq!(
+            to_json(&parse_op(r#"put k "two words""#).unwrap()),
+            to_json(&RequestOp::Put(PutRequest::new("k", "two words")))
+        );
+        assert_eq!(
+            to_json(&parse_op("get k --prefix").unwrap()),
+            to_json(&RequestOp::Range(RangeRequest::new_for_prefix("k")))
+        );
+        // The empty prefix selects every key rather than panicking.
+        assert_eq!(
+            to_json(&parse_op(r#"del "" --prefix"#).unwrap()),
+            json!({"request_delete_range": {"key": "AA==", "range_end": "AA==", "prev_kv": null}})
+        );
+        assert!(parse_op("put k").is_err());
+        assert!(parse_op("watch k").is_err());
+    }
+
+    #[test]
+    fn parse_txn_test() {
+        let txn = parse_txn(
+            r#"mod("counter") = "4"
+               value("owner") = "me"
+
+               put counter 5
+
+               get counter
+               del stale --prefix"#,
+        )
+        .unwrap();
+        let expected = TxnRequest::new(
+            vec![
+                Compare::new_mod_revision("counter", CompareResult::EQUAL, 4),
+                Compare::new_value("owner", CompareResult::EQUAL, "me"),
+            ],
+            vec![RequestOp::Put(PutRequest::new("counter", "5"))],
+            vec![
+                RequestOp::Range(RangeRequest::new("counter")),
+                RequestOp::DeleteRange(DeleteRangeRequest::new_for_prefix("stale")),
+            ],
+        );
+        assert_eq!(to_json(&txn), to_json(&expected));
+        // Missing sections are empty.
+        let txn = parse_txn("\nput k v\n").unwrap();
+        assert_eq!(
+            to_json(&txn),
+            to_json(&TxnRequest::new(
+                vec![],
+                vec![RequestOp::Put(PutRequest::new("k", "v"))],
+                vec![]
+            ))
+        );
+        assert!(parse_txn("\nput k v\n\nget k\n\nget k").is_err());
+        assert!(parse_txn("put k v").is_err());
+    }
+
+    #[test]
+    fn table_test() {
+        let mut report = Report::new(&["KEY", "VALUE"]);
+        report
+            .rows
+            .push(vec![String::from("a"), String::from("ünïcode")]);
+        report
+            .rows
+            .push(vec![String::from("longer key"), String::new()]);
+        assert_eq!(
+            report.table(),
+            "+------------+---------+\n\
+             | KEY        | VALUE   |\n\
+             +------------+---------+\n\
+             | a          | ünïcode |\n\
+             | longer key |         |\n\
+             +------------+---------+\n"
+        );
+    }
+}
@@ -0,0 +1,150 @@
+//! A synchronous client for code that does not run an async runtime of its own, e.g., command
+//! line tools and build scripts. Every call blocks the current thread until `etcd` answers.
+//! Calls must not be made from within an async context, since they would block the executor.
+
+use super::etcd_actions::{EtcdSession, LeaseKeeper};
+use super::etcd_error::Result;
+use super::etcd_proto::*;
+use super::etcd_range::{RangeOptions, RangeResult};
+use futures::stream::BoxStream;
+use futures::{Future, TryStreamExt};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use tokio::runtime::{Builder, Runtime};
+
+/// A blocking `etcd` client owning the runtime its requests are driven on. Clones share both the
+/// connection pool and the runtime.
+#[derive(Clone)]
+pub struct Client {
+    session: EtcdSession,
+    runtime: Arc<Runtime>,
+}
+
+impl Client {
+    pub fn new(uri: &str) -> Result<Client> {
+        // A worker thread keeps background work such as lease refreshes going between calls.
+        let runtime = Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()?;
+        let session = {
+            let _context = runtime.enter();
+            EtcdSession::new(uri)
+        };
+        Ok(Client {
+            session,
+            runtime: Arc::new(runtime),
+        })
+    }
+
+    /// The async session this client wraps.
+    pub fn session(&self) -> &EtcdSession {
+        &self.session
+    }
+
+    /// Run `work` to completion on this client's runtime.
+    pub fn block_on<F: Future>(&self, work: F) -> F::Output {
+        self.runtime.block_on(work)
+    }
+
+    pub fn put(&self, key: &str, val: &str) -> Result<bool> {
+        self.block_on(self.session.put(key, val))
+    }
+
+    pub fn put_with_lease(&self, key: &str, val: &str, lease: i64) -> Result<bool> {
+        self.block_on(self.session.put_with_lease(key, val, lease))
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<String>> {
+        self.block_on(self.session.get(key))
+    }
+
+    pub fn get_prefix(&self, key: &str) -> Result<Vec<(String, String)>> {
+        self.block_on(self.session.get_prefix(key))
+    }
+
+    /// Read the keys selected by `opts`.
+    pub fn range(&self, opts: &RangeOptions) -> Result<RangeResult> {
+        self.block_on(self.session.range(opts))
+    }
+
+    /// Delete a key, returning the number of keys deleted.
+    pub fn delete(&self, key: &str) -> Result<usize> {
+        self.block_on(self.session.delete(key))
+    }
+
+    /// Delete every key starting with `prefix`, returning the number of keys deleted.
+    pub fn delete_prefix(&self, prefix: &str) -> Result<usize> {
+        self.block_on(self.session.delete_prefix(prefix))
+    }
+
+    pub fn txn(&self, req: &TxnRequest) -> Result<TxnResponse> {
+        self.block_on(self.session.txn(req))
+    }
+
+    /// Grant a lease that expires after `ttl` seconds unless kept alive.
+    pub fn lease_grant(&self, ttl: i64) -> Result<LeaseGrantResponse> {
+        self.block_on(self.session.lease_grant(ttl))
+    }
+
+    /// Revoke a lease, deleting every key attached to it.
+    pub fn lease_revoke(&self, id: i64) -> Result<()> {
+        self.block_on(self.session.lease_revoke(id))
+    }
+
+    /// Refresh a lease once.
+    pub fn lease_keep_alive(&self, id: i64) -> Result<LeaseKeepAliveResponse> {
+        self.block_on(self.session.lease_keep_alive(id))
+    }
+
+    /// Refresh a lease on this client's runtime until the returned `LeaseKeeper` is dropped.
+    pub fn keep_alive(&self, id: i64, ttl: i64) -> LeaseKeeper {
+        let _context = self.runtime.enter();
+        self.session.keep_alive(id, ttl)
+    }
+
+    /// Watch a key, returning an iterator over its events.
+    pub fn watch(&self, key: &str) -> Result<Watcher> {
+        self.watch_with(WatchCreateRequest::new_for_key(key))
+    }
+
+    /// Watch every key starting with `prefix`, returning an iterator over their events.
+    pub fn watch_pfx(&self, prefix: &str) -> Result<Watcher> {
+        self.watch_with(WatchCreateRequest::new_for_prefix(prefix))
+    }
+
+    /// Start an arbitrary watch, e.g., one starting at a past revision.
+    pub fn watch_with(&self, create_request: WatchCreateRequest) -> Result<Watcher> {
+        let stream = self.block_on(self.session.watch_with(create_request))?;
+        Ok(Watcher {
+            stream,
+            runtime: self.runtime.clone(),
+            pending: VecDeque::new(),
+        })
+    }
+}
+
+/// Blocking iterator over the events of a watch. Each call to `next` waits until an event arrives
+/// and the iterator ends once `etcd` closes the watch.
+pub struct Watcher {
+    stream: BoxStream<'static, Result<WatchResponse>>,
+    runtime: Arc<Runtime>,
+    pending: VecDeque<Event>,
+}
+
+impl Iterator for Watcher {
+    type Item = Result<Event>;
+
+    fn next(&mut self) -> Option<Result<Event>> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Some(Ok(event));
+            }
+            match self.runtime.block_on(self.stream.try_next()) {
+                Ok(Some(resp)) => self.pending.extend(resp.events.unwrap_or_default()),
+                Ok(None) => return None,
+                Err(err) => return Some(Err(err)),
+            }
+        }
+    }
+}
@@ -1,183 +1,644 @@
+use super::etcd_codec::{Codec, Json, Typed, TypedEvent};
+use super::etcd_error::{Error, Result};
+use super::etcd_kv::Watch;
+use super::etcd_namespace::Namespace;
 use super::etcd_proto::*;
-use serde_json;
-use hyper;
-use tokio_core;
-use futures::Future;
-use futures::stream::Stream;
+use super::etcd_range::{RangeOptions, RangeResult};
+use super::etcd_transport::{HttpTransport, Transport};
+use bytes::{Bytes, BytesMut};
+use futures::channel::oneshot;
+use futures::future::{self, Shared};
+use futures::stream::{self, BoxStream};
+use futures::{Future, FutureExt, StreamExt, TryStreamExt};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::cmp;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
 
-const PUT_ENDPOINT: &str = "/v3alpha/kv/put";
-const RANGE_ENDPOINT: &str = "/v3alpha/kv/range";
-const WATCH_ENDPOINT: &str = "/v3alpha/watch";
+pub(crate) const PUT_ENDPOINT: &str = "/v3alpha/kv/put";
+pub(crate) const RANGE_ENDPOINT: &str = "/v3alpha/kv/range";
+pub(crate) const DELETE_RANGE_ENDPOINT: &str = "/v3alpha/kv/deleterange";
+pub(crate) const TXN_ENDPOINT: &str = "/v3alpha/kv/txn";
+pub(crate) const WATCH_ENDPOINT: &str = "/v3alpha/watch";
+pub(crate) const LEASE_GRANT_ENDPOINT: &str = "/v3alpha/lease/grant";
+pub(crate) const LEASE_REVOKE_ENDPOINT: &str = "/v3alpha/kv/lease/revoke";
+pub(crate) const LEASE_KEEPALIVE_ENDPOINT: &str = "/v3alpha/lease/keepalive";
+pub(crate) const LOCK_ENDPOINT: &str = "/v3alpha/lock/lock";
+pub(crate) const UNLOCK_ENDPOINT: &str = "/v3alpha/lock/unlock";
+pub(crate) const CAMPAIGN_ENDPOINT: &str = "/v3alpha/election/campaign";
+pub(crate) const PROCLAIM_ENDPOINT: &str = "/v3alpha/election/proclaim";
+pub(crate) const LEADER_ENDPOINT: &str = "/v3alpha/election/leader";
+pub(crate) const OBSERVE_ENDPOINT: &str = "/v3alpha/election/observe";
+pub(crate) const RESIGN_ENDPOINT: &str = "/v3alpha/election/resign";
+pub(crate) const AUTHENTICATE_ENDPOINT: &str = "/v3alpha/auth/authenticate";
+pub(crate) const MEMBER_LIST_ENDPOINT: &str = "/v3alpha/cluster/member/list";
+pub(crate) const STATUS_ENDPOINT: &str = "/v3alpha/maintenance/status";
 
+/// Handle to an `etcd` gateway. Clones are cheap and share the connection pool, the open watches
+/// and the authentication token, so a single session can serve every task of a service.
+#[derive(Clone)]
 pub struct EtcdSession {
-    client: hyper::Client<hyper::client::HttpConnector>,
-    _handle: tokio_core::reactor::Handle,
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    transport: Box<dyn Transport>,
     uri: String,
+    token: RwLock<Option<String>>,
+    watches: Watches,
+}
+
+/// The error for a watch that ended before the event it was waiting for, e.g., because it was
+/// cancelled or the gateway went away.
+pub(crate) fn watch_closed() -> Error {
+    Error::Io(io::Error::new(
+        io::ErrorKind::ConnectionAborted,
+        "watch ended before the awaited event",
+    ))
+}
+
+/// The result of a streamed message, or the error the gateway sent in its place.
+fn stream_result<T>(result: Option<T>, error: Option<StreamError>) -> Result<T> {
+    result.ok_or_else(|| {
+        let message = error
+            .and_then(|err| err.message)
+            .unwrap_or_else(|| String::from("message without a result"));
+        Error::Io(io::Error::other(format!("etcd stream error: {}", message)))
+    })
+}
+
+/// Resolves once a key selected by `create_request` is deleted at or after revision `rev`, on
+/// anything that can watch. Fails if the watch ends first.
+pub(crate) async fn wait_deletion<W: Watch + ?Sized>(
+    watch: &W,
+    create_request: WatchCreateRequest,
+    rev: i64,
+) -> Result<()> {
+    let create_request = WatchCreateRequest {
+        start_revision: Some(rev.to_string()),
+        filters: Some(vec![FilterType::NOPUT]),
+        ..create_request
+    };
+    let mut stream = watch.watch_with(create_request).await?;
+    while let Some(resp) = stream.try_next().await? {
+        let deleted = resp.events.as_ref().is_some_and(|events| {
+            events
+                .iter()
+                .any(|ev| ev.event_type() == &Some(EventType::DELETE))
+        });
+        if deleted {
+            return Ok(());
+        }
+    }
+    Err(watch_closed())
+}
+
+/// Keeps track of the watch streams opened through a session, so they can be cancelled together.
+struct Watches {
+    active: AtomicUsize,
+    cancel: Mutex<(oneshot::Sender<()>, Shared<oneshot::Receiver<()>>)>,
+}
+
+impl Watches {
+    fn new() -> Watches {
+        let (cancel, cancelled) = oneshot::channel();
+        Watches {
+            active: AtomicUsize::new(0),
+            cancel: Mutex::new((cancel, cancelled.shared())),
+        }
+    }
+
+    /// Resolves once `cancel_all` is called.
+    fn cancelled(&self) -> Shared<oneshot::Receiver<()>> {
+        self.cancel.lock().unwrap().1.clone()
+    }
+
+    fn cancel_all(&self) {
+        let (cancel, cancelled) = oneshot::channel();
+        // Dropping the previous sender ends every watch started before this call.
+        *self.cancel.lock().unwrap() = (cancel, cancelled.shared());
+    }
+}
+
+/// Counts a watch stream as active for as long as it is alive.
+struct ActiveWatch(Arc<Inner>);
+
+impl ActiveWatch {
+    fn new(inner: &Arc<Inner>) -> ActiveWatch {
+        inner.watches.active.fetch_add(1, Ordering::SeqCst);
+        ActiveWatch(inner.clone())
+    }
+}
+
+impl Drop for ActiveWatch {
+    fn drop(&mut self) {
+        self.0.watches.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Refreshes a lease in the background for as long as it is held.
+pub struct LeaseKeeper {
+    id: i64,
+    done: Shared<oneshot::Receiver<()>>,
+    _stop: oneshot::Sender<()>,
+}
+
+impl LeaseKeeper {
+    pub fn lease_id(&self) -> i64 {
+        self.id
+    }
+
+    /// Resolves once the lease is no longer being refreshed, e.g., because it expired.
+    pub fn done(&self) -> impl Future<Output = ()> + Send + 'static {
+        self.done.clone().map(|_| ())
+    }
 }
 
 impl EtcdSession {
-    pub fn new(handle: &tokio_core::reactor::Handle, uri: &str) -> EtcdSession {
+    pub fn new(uri: &str) -> EtcdSession {
+        EtcdSession::with_transport(uri, HttpTransport::new())
+    }
+
+    /// A session that sends its requests through `transport`, e.g., a `Recorder` or `Replayer`.
+    pub fn with_transport<T: Transport + 'static>(uri: &str, transport: T) -> EtcdSession {
         EtcdSession {
-            client: hyper::Client::new(handle),
-            _handle: handle.clone(),
-            uri: String::from(uri),
+            inner: Arc::new(Inner {
+                transport: Box::new(transport),
+                uri: String::from(uri),
+                token: RwLock::new(None),
+                watches: Watches::new(),
+            }),
         }
     }
 
-    // FIXME: Consider just using `into` trait by marking this as nightly only.
-    pub fn put(&self, key: &str, val: &str) -> Box<Future<Error = hyper::Error, Item = bool>> {
-        let uri = format!("{}{}", self.uri, PUT_ENDPOINT)
-            .parse::<hyper::Uri>()
-            .unwrap();
-        let mut put_request = hyper::Request::new(hyper::Method::Post, uri);
-        put_request.set_body(serde_json::to_string(&PutRequest::new(key, val)).unwrap());
-        Box::new(self.client.request(put_request).and_then(
-            |res| if res.status() ==
-                hyper::StatusCode::Ok
-            {
-                Ok(true)
-            } else {
-                Err(hyper::Error::Status)
-            },
-        ))
-    }
-
-    pub fn get(&self, key: &str) -> Box<Future<Error = hyper::Error, Item = Option<String>>> {
-        let uri = format!("{}{}", self.uri, RANGE_ENDPOINT)
-            .parse::<hyper::Uri>()
-            .unwrap();
-
-        let mut range_request = hyper::Request::new(hyper::Method::Post, uri);
-        range_request.set_body(serde_json::to_string(&RangeRequest::new(key)).unwrap());
-        Box::new(
-            self.client
-                .request(range_request)
-                .and_then(|res| res.body().concat2())
-                .and_then(|body| {
-                    let v: RangeResponse = serde_json::from_slice(&body).unwrap();
-                    if v.count() == 1 {
-                        Ok(v.kvs.as_ref().unwrap()[0].value().clone())
-                    } else {
-                        Ok(None)
+    /// Authenticate as `name`, every clone of this session then sends requests as that user.
+    pub async fn authenticate(&self, name: &str, password: &str) -> Result<()> {
+        let resp: AuthenticateResponse = self
+            .call(
+                AUTHENTICATE_ENDPOINT,
+                &AuthenticateRequest::new(name, password),
+            )
+            .await?;
+        *self.inner.token.write().unwrap() = resp.token;
+        Ok(())
+    }
+
+    /// Number of watch streams opened through this session that are still alive.
+    pub fn active_watches(&self) -> usize {
+        self.inner.watches.active.load(Ordering::SeqCst)
+    }
+
+    /// End every watch stream opened through this session so far, e.g., before shutting down.
+    pub fn cancel_watches(&self) {
+        self.inner.watches.cancel_all()
+    }
+
+    /// Run `work` in the background, e.g., releasing a guard that was dropped. This is a no-op
+    /// outside of a Tokio runtime.
+    pub fn spawn<F>(&self, work: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            runtime.spawn(work);
+        }
+    }
+
+    /// A view of this session where every key is relative to `prefix`.
+    pub fn namespace(&self, prefix: &str) -> Namespace {
+        Namespace::new(self, prefix)
+    }
+
+    /// A view of this session that stores values of type `T` encoded with `codec`.
+    pub fn typed<T, C>(&self, codec: C) -> Typed<T, C>
+    where
+        C: Codec + Clone + Send + 'static,
+    {
+        Typed::new(self, codec)
+    }
+
+    /// Issue a request against the gateway, failing unless it answers with `200 OK`.
+    async fn post<Req: Serialize>(
+        &self,
+        endpoint: &str,
+        req: &Req,
+    ) -> Result<BoxStream<'static, Result<Bytes>>> {
+        let uri = format!("{}{}", self.inner.uri, endpoint);
+        let token = self.inner.token.read().unwrap().clone();
+        let body = Bytes::from(serde_json::to_vec(req)?);
+        let reply = self
+            .inner
+            .transport
+            .post(&uri, token.as_deref(), body)
+            .await?;
+        if reply.status == hyper::StatusCode::OK {
+            Ok(reply.body)
+        } else {
+            Err(Error::Status(reply.status))
+        }
+    }
+
+    /// Issue a unary request against the gateway and decode its response.
+    async fn call<Req, Resp>(&self, endpoint: &str, req: &Req) -> Result<Resp>
+    where
+        Req: Serialize,
+        Resp: DeserializeOwned,
+    {
+        let body = self
+            .post(endpoint, req)
+            .await?
+            .try_fold(BytesMut::new(), |mut buf, chunk| {
+                buf.extend_from_slice(&chunk);
+                future::ok(buf)
+            })
+            .await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// Issue a server-streaming request against the gateway, which answers with one JSON message
+    /// per line.
+    async fn call_streaming<Req, Resp>(
+        &self,
+        endpoint: &str,
+        req: &Req,
+    ) -> Result<BoxStream<'static, Result<Resp>>>
+    where
+        Req: Serialize,
+        Resp: DeserializeOwned + Send + 'static,
+    {
+        let body = self.post(endpoint, req).await?;
+        let messages = stream::unfold((body, BytesMut::new()), |(mut body, mut buf)| async move {
+            loop {
+                if let Some(end) = buf.iter().position(|&b| b == b'\n') {
+                    let line = buf.split_to(end + 1);
+                    if line.iter().all(u8::is_ascii_whitespace) {
+                        continue;
                     }
-                }),
-        )
+                    let msg = serde_json::from_slice(&line).map_err(Error::from);
+                    return Some((msg, (body, buf)));
+                }
+                match body.next().await {
+                    Some(Ok(data)) => buf.extend_from_slice(&data),
+                    Some(Err(err)) => return Some((Err(err), (body, buf))),
+                    // The last message need not be followed by a newline.
+                    None if buf.iter().any(|b| !b.is_ascii_whitespace()) => {
+                        let msg = serde_json::from_slice(&buf).map_err(Error::from);
+                        buf.clear();
+                        return Some((msg, (body, buf)));
+                    }
+                    None => return None,
+                }
+            }
+        });
+        Ok(messages.boxed())
     }
 
-    pub fn get_prefix(
+    pub async fn put(&self, key: &str, val: &str) -> Result<bool> {
+        self.call::<_, PutResponse>(PUT_ENDPOINT, &PutRequest::new(key, val))
+            .await
+            .map(|_| true)
+    }
+
+    /// Put a value that need not be UTF-8.
+    pub async fn put_bytes(&self, key: &str, val: &[u8]) -> Result<bool> {
+        self.call::<_, PutResponse>(PUT_ENDPOINT, &PutRequest::new_bytes(key, val))
+            .await
+            .map(|_| true)
+    }
+
+    /// Put `val` serialised as JSON.
+    pub async fn put_typed<T: Serialize>(&self, key: &str, val: &T) -> Result<bool> {
+        self.put_bytes(key, &Json.encode(val)?).await
+    }
+
+    /// Get a value stored as JSON.
+    pub async fn get_typed<T>(&self, key: &str) -> Result<Option<T>>
+    where
+        T: DeserializeOwned,
+    {
+        self.typed(Json).get(key).await
+    }
+
+    /// Get every value stored as JSON under keys starting with `key`.
+    pub async fn get_prefix_typed<T>(&self, key: &str) -> Result<Vec<(String, T)>>
+    where
+        T: DeserializeOwned,
+    {
+        self.typed(Json).get_prefix(key).await
+    }
+
+    /// Create a new stream of changes to keys starting with `key`, with values decoded as JSON.
+    pub async fn watch_pfx_typed<T>(
         &self,
         key: &str,
-    ) -> Box<Future<Error = hyper::Error, Item = Vec<(String, String)>>> {
-        let uri = format!("{}{}", self.uri, RANGE_ENDPOINT)
-            .parse::<hyper::Uri>()
-            .unwrap();
-
-        let mut range_request = hyper::Request::new(hyper::Method::Post, uri);
-        range_request.set_body(
-            serde_json::to_string(&RangeRequest::new_for_prefix(key)).unwrap(),
-        );
-        Box::new(
-            self.client
-                .request(range_request)
-                .and_then(|res| res.body().concat2())
-                .and_then(|body| {
-                    let v: RangeResponse = serde_json::from_slice(&body).unwrap();
-                    if v.count() > 0 {
-                        Ok(
-                            v.kvs
-                                .as_ref()
-                                .unwrap()
-                                .iter()
-                                .map(|kvs| {
-                                    (kvs.key().unwrap().clone(), kvs.value().unwrap().clone())
-                                })
-                                .collect(),
-                        )
-                    } else {
-                        Ok(vec![])
-                    }
-                }),
-        )
+    ) -> Result<BoxStream<'static, Result<TypedEvent<T>>>>
+    where
+        T: DeserializeOwned + Send + 'static,
+    {
+        self.typed(Json).watch_pfx(key).await
     }
 
-    pub fn get_prefix_raw(
+    /// Put a key that is deleted when `lease` expires or is revoked.
+    pub async fn put_with_lease(&self, key: &str, val: &str, lease: i64) -> Result<bool> {
+        self.call::<_, PutResponse>(PUT_ENDPOINT, &PutRequest::new_with_lease(key, val, lease))
+            .await
+            .map(|_| true)
+    }
+
+    pub async fn get(&self, key: &str) -> Result<Option<String>> {
+        let v = self.range_raw(&RangeRequest::new(key)).await?;
+        if v.count() == 1 {
+            Ok(v.kvs.as_ref().unwrap()[0].value())
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub async fn get_prefix(&self, key: &str) -> Result<Vec<(String, String)>> {
+        let v = self.get_prefix_raw(key).await?;
+        Ok(v.kvs
+            .unwrap_or_default()
+            .iter()
+            .map(|kv| (kv.key().unwrap(), kv.value().unwrap_or_default()))
+            .collect())
+    }
+
+    pub async fn get_prefix_raw(&self, prefix: &str) -> Result<RangeResponse> {
+        self.range_raw(&RangeRequest::new_for_prefix(prefix)).await
+    }
+
+    /// Create a stream of every key starting with `prefix`, fetched `page_size` keys at a time.
+    /// Every page is read at the revision of the first, so the result is a consistent snapshot
+    /// even if the prefix changes while it is being read.
+    pub fn scan_prefix(
         &self,
         prefix: &str,
-    ) -> Box<Future<Error = hyper::Error, Item = RangeResponse>> {
-        let uri = format!("{}{}", self.uri, RANGE_ENDPOINT)
-            .parse::<hyper::Uri>()
-            .unwrap();
-
-        let mut range_request = hyper::Request::new(hyper::Method::Post, uri);
-        range_request.set_body(
-            serde_json::to_string(&RangeRequest::new_for_prefix(prefix)).unwrap(),
-        );
-        Box::new(
-            self.client
-                .request(range_request)
-                .and_then(|res| res.body().concat2())
-                .and_then(|body| {
-                    let v: RangeResponse = serde_json::from_slice(&body).unwrap();
-                    Ok(v)
-                }),
-        )
+        page_size: usize,
+    ) -> BoxStream<'static, Result<KeyValue>> {
+        self.scan(prefix, page_size, None)
     }
 
-    /// Create a new stream that reports changes to a key.
-    pub fn watch(
+    /// Like `scan_prefix`, but every page is read at `revision`, which must not have been
+    /// compacted.
+    pub fn scan_prefix_at(
         &self,
-        key: &str,
-    ) -> Box<
-        Future<
-            Error = hyper::Error,
-            Item = Box<Stream<Item = WatchResponse, Error = hyper::Error>>,
-        >,
-    > {
-        let uri = format!("{}{}", self.uri, WATCH_ENDPOINT)
-            .parse::<hyper::Uri>()
-            .unwrap();
-        let mut watch_request = hyper::Request::new(hyper::Method::Post, uri);
-        watch_request.set_body(
-            serde_json::to_string(&WatchRequest::new_create_request(
-                WatchCreateRequest::new_for_key(key),
-            )).unwrap(),
-        );
-        Box::new(self.client.request(watch_request).and_then(|res| {
-            Ok(Box::new(res.body().map(|chunk| {
-                let outer: WatchStreamResponse = serde_json::from_slice(&chunk).unwrap();
-                let inner = outer.result.unwrap();
-                inner
-            })) as
-                Box<Stream<Item = WatchResponse, Error = hyper::Error>>)
-        }))
+        prefix: &str,
+        page_size: usize,
+        revision: i64,
+    ) -> BoxStream<'static, Result<KeyValue>> {
+        self.scan(prefix, page_size, Some(revision))
+    }
+
+    fn scan(
+        &self,
+        prefix: &str,
+        page_size: usize,
+        rev: Option<i64>,
+    ) -> BoxStream<'static, Result<KeyValue>> {
+        let session = self.clone();
+        let prefix = String::from(prefix);
+        // `None` once done, otherwise the key to continue after, which the first page lacks.
+        let start: (Option<Option<Vec<u8>>>, Option<i64>) = (Some(None), rev);
+        let pages = stream::try_unfold(start, move |(next, rev)| {
+            let session = session.clone();
+            let prefix = prefix.clone();
+            async move {
+                let after = match next {
+                    Some(after) => after,
+                    None => return Ok::<_, Error>(None),
+                };
+                let mut range_request = RangeRequest {
+                    limit: Some(page_size.to_string()),
+                    revision: rev.map(|rev| rev.to_string()),
+                    ..RangeRequest::new_for_prefix(&prefix)
+                };
+                if let Some(key) = after {
+                    range_request.key = Some(base64::encode(&key[..]));
+                }
+                let resp = session.range_raw(&range_request).await?;
+                let rev = rev.unwrap_or_else(|| resp.header.as_ref().unwrap().rev());
+                let more = resp.more.unwrap_or(false);
+                let kvs = resp.kvs.unwrap_or_default();
+                // The next page starts right after the last key of this one.
+                let next = if more {
+                    kvs.last().map(|kv| {
+                        let mut key = kv.key_as_u8().unwrap();
+                        key.push(0);
+                        Some(key)
+                    })
+                } else {
+                    None
+                };
+                Ok(Some((
+                    stream::iter(kvs.into_iter().map(Ok)),
+                    (next, Some(rev)),
+                )))
+            }
+        });
+        pages.try_flatten().boxed()
     }
 
     /// Create a new stream that reports changes to a key.
-    pub fn watch_pfx(
+    pub async fn watch(&self, key: &str) -> Result<BoxStream<'static, Result<WatchResponse>>> {
+        self.watch_with(WatchCreateRequest::new_for_key(key)).await
+    }
+
+    /// Create a new stream that reports changes to a key.
+    pub async fn watch_pfx(&self, key: &str) -> Result<BoxStream<'static, Result<WatchResponse>>> {
+        self.watch_with(WatchCreateRequest::new_for_prefix(key))
+            .await
+    }
+
+    /// Create a new stream for an arbitrary watch, e.g., one starting at a past revision.
+    pub async fn watch_with(
         &self,
-        key: &str,
-    ) -> Box<
-        Future<
-            Error = hyper::Error,
-            Item = Box<Stream<Item = WatchResponse, Error = hyper::Error>>,
-        >,
-    > {
-        let uri = format!("{}{}", self.uri, WATCH_ENDPOINT)
-            .parse::<hyper::Uri>()
-            .unwrap();
-        let mut watch_request = hyper::Request::new(hyper::Method::Post, uri);
-        watch_request.set_body(
-            serde_json::to_string(&WatchRequest::new_create_request(
-                WatchCreateRequest::new_for_prefix(key),
-            )).unwrap(),
-        );
-        Box::new(self.client.request(watch_request).and_then(|res| {
-            Ok(Box::new(res.body().map(|chunk| {
-                let outer: WatchStreamResponse = serde_json::from_slice(&chunk).unwrap();
-                let inner = outer.result.unwrap();
-                inner
-            })) as
-                Box<Stream<Item = WatchResponse, Error = hyper::Error>>)
-        }))
+        create_request: WatchCreateRequest,
+    ) -> Result<BoxStream<'static, Result<WatchResponse>>> {
+        let request = WatchRequest::new_create_request(create_request);
+        let stream = self
+            .call_streaming::<_, WatchStreamResponse>(WATCH_ENDPOINT, &request)
+            .await?;
+        let active = ActiveWatch::new(&self.inner);
+        Ok(stream
+            .map(|outer| outer.and_then(|outer| stream_result(outer.result, outer.error)))
+            .take_until(self.inner.watches.cancelled())
+            .map(move |resp| {
+                let _ = &active;
+                resp
+            })
+            .boxed())
+    }
+
+    /// Resolves once `key` is deleted at or after revision `rev`. Fails if the watch ends first,
+    /// e.g., after `cancel_watches`.
+    pub async fn wait_delete(&self, key: &str, rev: i64) -> Result<()> {
+        wait_deletion(self, WatchCreateRequest::new_for_key(key), rev).await
+    }
+
+    /// Resolves once any key starting with `prefix` is deleted at or after revision `rev`. Fails
+    /// if the watch ends first.
+    pub async fn wait_delete_pfx(&self, prefix: &str, rev: i64) -> Result<()> {
+        wait_deletion(self, WatchCreateRequest::new_for_prefix(prefix), rev).await
+    }
+
+    /// Read the keys selected by `opts`.
+    pub async fn range(&self, opts: &RangeOptions) -> Result<RangeResult> {
+        self.range_raw(opts.request()).await.map(RangeResult::from)
+    }
+
+    /// Issue an arbitrary `PutRequest`, e.g., one returning the previous value.
+    pub async fn put_raw(&self, req: &PutRequest) -> Result<PutResponse> {
+        self.call(PUT_ENDPOINT, req).await
+    }
+
+    /// Issue an arbitrary `RangeRequest`.
+    pub async fn range_raw(&self, req: &RangeRequest) -> Result<RangeResponse> {
+        self.call(RANGE_ENDPOINT, req).await
+    }
+
+    /// Delete a key, returning the number of keys deleted.
+    pub async fn delete(&self, key: &str) -> Result<usize> {
+        self.call::<_, DeleteRangeResponse>(DELETE_RANGE_ENDPOINT, &DeleteRangeRequest::new(key))
+            .await
+            .map(|resp| resp.deleted())
+    }
+
+    /// Delete every key starting with `prefix`, returning the number of keys deleted.
+    pub async fn delete_prefix(&self, prefix: &str) -> Result<usize> {
+        self.call::<_, DeleteRangeResponse>(
+            DELETE_RANGE_ENDPOINT,
+            &DeleteRangeRequest::new_for_prefix(prefix),
+        )
+        .await
+        .map(|resp| resp.deleted())
+    }
+
+    /// Issue an arbitrary `DeleteRangeRequest`, e.g., one returning the deleted keys.
+    pub async fn delete_range_raw(&self, req: &DeleteRangeRequest) -> Result<DeleteRangeResponse> {
+        self.call(DELETE_RANGE_ENDPOINT, req).await
+    }
+
+    /// Execute a transaction.
+    pub async fn txn(&self, req: &TxnRequest) -> Result<TxnResponse> {
+        self.call(TXN_ENDPOINT, req).await
+    }
+
+    /// Grant a lease that expires after `ttl` seconds unless kept alive.
+    pub async fn lease_grant(&self, ttl: i64) -> Result<LeaseGrantResponse> {
+        self.call(LEASE_GRANT_ENDPOINT, &LeaseGrantRequest::new(ttl))
+            .await
+    }
+
+    /// Revoke a lease, deleting every key attached to it.
+    pub async fn lease_revoke(&self, id: i64) -> Result<()> {
+        self.call::<_, LeaseRevokeResponse>(LEASE_REVOKE_ENDPOINT, &LeaseRevokeRequest::new(id))
+            .await
+            .map(|_| ())
+    }
+
+    /// Refresh a lease once.
+    pub async fn lease_keep_alive(&self, id: i64) -> Result<LeaseKeepAliveResponse> {
+        self.call::<_, LeaseKeepAliveStreamResponse>(
+            LEASE_KEEPALIVE_ENDPOINT,
+            &LeaseKeepAliveRequest::new(id),
+        )
+        .await
+        .and_then(|outer| stream_result(outer.result, outer.error))
+    }
+
+    /// Refresh a lease every third of its TTL until the returned `LeaseKeeper` is dropped or the
+    /// lease expires. Failed refreshes are retried on the next tick. Must be called from within
+    /// a Tokio runtime.
+    pub fn keep_alive(&self, id: i64, ttl: i64) -> LeaseKeeper {
+        let (stop, stopped) = oneshot::channel::<()>();
+        let (lost, done) = oneshot::channel::<()>();
+        let session = self.clone();
+        let period = Duration::from_secs(cmp::max(ttl / 3, 1) as u64);
+        let refresh = async move {
+            let start = tokio::time::Instant::now() + period;
+            let mut ticks = tokio::time::interval_at(start, period);
+            loop {
+                ticks.tick().await;
+                // `etcd` reports a TTL of zero for leases that no longer exist.
+                if let Ok(resp) = session.lease_keep_alive(id).await {
+                    if resp.ttl() <= 0 {
+                        break;
+                    }
+                }
+            }
+        };
+        tokio::spawn(async move {
+            future::select(Box::pin(refresh), stopped).await;
+            drop(lost);
+        });
+        LeaseKeeper {
+            id,
+            done: done.shared(),
+            _stop: stop,
+        }
+    }
+
+    /// Acquire the lock `name` using the `v3lock` service, holding it for as long as `lease` is
+    /// alive. Resolves once the lock is held.
+    pub async fn lock(&self, name: &str, lease: i64) -> Result<LockResponse> {
+        self.call(LOCK_ENDPOINT, &LockRequest::new(name, lease))
+            .await
+    }
+
+    /// Release a lock given the key returned by `lock`.
+    pub async fn unlock(&self, key: &str) -> Result<()> {
+        self.call::<_, UnlockResponse>(UNLOCK_ENDPOINT, &UnlockRequest::new(key))
+            .await
+            .map(|_| ())
+    }
+
+    /// Campaign in the election `name`, resolving once this campaign is the leader. Leadership is
+    /// held for as long as `lease` is alive.
+    pub async fn campaign(&self, name: &str, lease: i64, value: &str) -> Result<CampaignResponse> {
+        self.call(CAMPAIGN_ENDPOINT, &CampaignRequest::new(name, lease, value))
+            .await
+    }
+
+    /// Update the value announced by a leader without another election.
+    pub async fn proclaim(&self, leader: &LeaderKey, value: &str) -> Result<()> {
+        self.call::<_, ProclaimResponse>(PROCLAIM_ENDPOINT, &ProclaimRequest::new(leader, value))
+            .await
+            .map(|_| ())
+    }
+
+    /// Current leader of the election `name`. `etcd` reports an error if there is none.
+    pub async fn leader(&self, name: &str) -> Result<LeaderResponse> {
+        self.call(LEADER_ENDPOINT, &LeaderRequest::new(name)).await
+    }
+
+    /// Create a new stream reporting every change of leader for the election `name`.
+    pub async fn observe(&self, name: &str) -> Result<BoxStream<'static, Result<LeaderResponse>>> {
+        let stream = self
+            .call_streaming::<_, LeaderStreamResponse>(OBSERVE_ENDPOINT, &LeaderRequest::new(name))
+            .await?;
+        Ok(stream
+            .map(|outer| outer.and_then(|outer| stream_result(outer.result, outer.error)))
+            .boxed())
+    }
+
+    /// Give up leadership, letting the next campaign win.
+    pub async fn resign(&self, leader: &LeaderKey) -> Result<()> {
+        self.call::<_, ResignResponse>(RESIGN_ENDPOINT, &ResignRequest::new(leader))
+            .await
+            .map(|_| ())
+    }
+
+    /// List the members of the cluster.
+    pub async fn member_list(&self) -> Result<MemberListResponse> {
+        self.call(MEMBER_LIST_ENDPOINT, &MemberListRequest::default())
+            .await
+    }
+
+    /// Status of the member this session talks to.
+    pub async fn status(&self) -> Result<StatusResponse> {
+        self.call(STATUS_ENDPOINT, &StatusRequest::default()).await
     }
 }
@@ -0,0 +1,200 @@
+use super::etcd_actions::EtcdSession;
+use super::etcd_concurrency::{unique_key, HeldLease, LeaseMode, Session};
+use super::etcd_error::{Error, Result};
+use super::etcd_lock::{claim_key, spawn_abandon};
+use super::etcd_proto::*;
+use super::etcd_queue::{put_new, wait_put};
+use futures::Future;
+use std::io;
+use std::time::Duration;
+
+/// A barrier that blocks waiters for as long as a single key exists.
+pub struct Barrier {
+    session: EtcdSession,
+    key: String,
+}
+
+impl Barrier {
+    pub fn new(session: &EtcdSession, key: &str) -> Barrier {
+        Barrier {
+            session: session.clone(),
+            key: String::from(key),
+        }
+    }
+
+    /// Raise the barrier, resolving to false if it was already held.
+    pub async fn hold(&self) -> Result<bool> {
+        put_new(&self.session, &self.key, "").await
+    }
+
+    /// Lower the barrier, letting every waiter through.
+    pub async fn release(&self) -> Result<()> {
+        self.session.delete(&self.key).await.map(|_| ())
+    }
+
+    /// Resolves once the barrier is released, or fails with `Error::Timeout` if that takes longer
+    /// than `timeout`. Fails as well if the watch on the key ends, e.g., after
+    /// `EtcdSession::cancel_watches`.
+    pub async fn wait(&self, timeout: Duration) -> Result<()> {
+        with_timeout(timeout, async {
+            // Check the key again after every deletion rather than trusting the watch alone.
+            loop {
+                let resp = self
+                    .session
+                    .range_raw(&RangeRequest::new(&self.key))
+                    .await?;
+                let rev = resp.header.as_ref().unwrap().rev();
+                if resp.count() == 0 {
+                    return Ok(());
+                }
+                self.session.wait_delete(&self.key, rev + 1).await?;
+            }
+        })
+        .await
+    }
+}
+
+/// A barrier that lets `count` participants enter together, and then leave together.
+/// Participants register lease-attached keys under `<key>/waiters/`, the last to enter creates
+/// `<key>/ready`, and leaving participants wait for each other's keys to be deleted.
+pub struct DoubleBarrier {
+    session: EtcdSession,
+    prefix: String,
+    count: usize,
+    lease: LeaseMode,
+}
+
+impl DoubleBarrier {
+    /// Create a barrier for `count` participants, each holding its own lease of `ttl` seconds.
+    pub fn new(session: &EtcdSession, key: &str, count: usize, ttl: i64) -> DoubleBarrier {
+        DoubleBarrier {
+            session: session.clone(),
+            prefix: format!("{}/", key.trim_end_matches('/')),
+            count,
+            lease: LeaseMode::PerHolder(ttl),
+        }
+    }
+
+    /// Create a barrier for `count` participants attached to the lease of `session`.
+    pub fn with_session(session: &Session, key: &str, count: usize) -> DoubleBarrier {
+        DoubleBarrier {
+            session: session.client().clone(),
+            prefix: format!("{}/", key.trim_end_matches('/')),
+            count,
+            lease: LeaseMode::from_session(session),
+        }
+    }
+
+    /// Register as a participant, resolving once `count` participants have entered. Fails with
+    /// `Error::Timeout` if that takes longer than `timeout`, or if the watch for the last
+    /// participant ends before it enters.
+    pub async fn enter(&self, timeout: Duration) -> Result<Participant> {
+        with_timeout(timeout, async {
+            let lease = self.lease.acquire(&self.session).await?;
+            let waiters = format!("{}waiters/", self.prefix);
+            let id = lease.id();
+            // On failure the participant is dropped, which removes its key.
+            let participant = Participant {
+                session: self.session.clone(),
+                prefix: self.prefix.clone(),
+                key: unique_key(&waiters, id),
+                lease: Some(lease),
+            };
+            let rev = claim_key(&self.session, &participant.key, id).await?;
+            let mut range_request = RangeRequest::new_for_prefix(&waiters);
+            range_request.count_only = Some(true);
+            let resp = self.session.range_raw(&range_request).await?;
+            let ready = format!("{}ready", self.prefix);
+            if resp.count() > self.count {
+                return Err(Error::Io(io::Error::other("Too many participants")));
+            } else if resp.count() == self.count {
+                self.session.put(&ready, "").await?;
+            } else {
+                // Only puts after we registered can be meant for us.
+                wait_put(&self.session, &ready, rev).await?;
+            }
+            Ok(participant)
+        })
+        .await
+    }
+}
+
+/// A participant that has entered a `DoubleBarrier`. Dropping it without calling `leave` removes
+/// its key without waiting for anybody else.
+pub struct Participant {
+    session: EtcdSession,
+    prefix: String,
+    key: String,
+    lease: Option<HeldLease>,
+}
+
+impl Participant {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Resolves once every participant has left. Fails with `Error::Timeout` if that takes
+    /// longer than `timeout`.
+    pub async fn leave(mut self, timeout: Duration) -> Result<()> {
+        // The lease has to be kept alive until everybody is done, so it is taken out of `self`.
+        let lease = self.lease.take().unwrap();
+        let session = &self.session;
+        let prefix = &self.prefix;
+        let key = &self.key;
+        with_timeout(timeout, async {
+            let mut range_request = RangeRequest::new_for_prefix_with_sort(
+                &format!("{}waiters/", prefix),
+                SortOrder::ASCEND,
+                SortTarget::KEY,
+            );
+            range_request.keys_only = Some(true);
+            loop {
+                let resp = session.range_raw(&range_request).await?;
+                let rev = resp.header.as_ref().unwrap().rev();
+                let keys: Vec<String> = resp
+                    .kvs
+                    .as_ref()
+                    .map(|kvs| kvs.iter().map(|kv| kv.key().unwrap()).collect())
+                    .unwrap_or_default();
+                if keys.is_empty() {
+                    break;
+                }
+                let lowest = &keys[0];
+                let highest = &keys[keys.len() - 1];
+                if keys.len() == 1 && lowest == key {
+                    // Last one out resets the barrier.
+                    session.delete(&format!("{}ready", prefix)).await?;
+                    session.delete(key).await?;
+                    break;
+                } else if lowest == key {
+                    // The lowest participant leaves last, waiting on the highest in turn.
+                    session.wait_delete(highest, rev + 1).await?;
+                } else {
+                    session.delete(key).await?;
+                    session.wait_delete(lowest, rev + 1).await?;
+                }
+            }
+            lease.release(session).await
+        })
+        .await
+    }
+}
+
+impl Drop for Participant {
+    fn drop(&mut self) {
+        if let Some(ref lease) = self.lease {
+            spawn_abandon(&self.session, &self.key, lease);
+        }
+    }
+}
+
+/// Fail `work` with `Error::Timeout` unless it completes within `timeout`.
+pub(crate) async fn with_timeout<T, F>(timeout: Duration, work: F) -> Result<T>
+where
+    F: Future<Output = Result<T>>,
+{
+    match tokio::time::timeout(timeout, work).await {
+        Ok(res) => res,
+        Err(_) => Err(Error::Timeout),
+    }
+}
@@ -0,0 +1,201 @@
+use super::etcd_actions::EtcdSession;
+use super::etcd_error::{Error, Result};
+use super::etcd_proto::*;
+use futures::stream::{self, BoxStream};
+use futures::{StreamExt, TryStreamExt};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::io;
+use std::marker::PhantomData;
+
+/// Serialisation format for values stored through `Typed`.
+pub trait Codec {
+    fn encode<T: Serialize>(&self, val: &T) -> io::Result<Vec<u8>>;
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> io::Result<T>;
+}
+
+fn invalid<E: ::std::error::Error + Send + Sync + 'static>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+/// Values as JSON, which keeps them readable with `etcdctl`.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Json;
+
+impl Codec for Json {
+    fn encode<T: Serialize>(&self, val: &T) -> io::Result<Vec<u8>> {
+        serde_json::to_vec(val).map_err(invalid)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> io::Result<T> {
+        serde_json::from_slice(bytes).map_err(invalid)
+    }
+}
+
+#[cfg(feature = "bincode")]
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Bincode;
+
+#[cfg(feature = "bincode")]
+impl Codec for Bincode {
+    fn encode<T: Serialize>(&self, val: &T) -> io::Result<Vec<u8>> {
+        bincode::serialize(val).map_err(invalid)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> io::Result<T> {
+        bincode::deserialize(bytes).map_err(invalid)
+    }
+}
+
+#[cfg(feature = "msgpack")]
+#[derive(Clone, Copy, Default, Debug)]
+pub struct MessagePack;
+
+#[cfg(feature = "msgpack")]
+impl Codec for MessagePack {
+    fn encode<T: Serialize>(&self, val: &T) -> io::Result<Vec<u8>> {
+        rmp_serde::to_vec(val).map_err(invalid)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> io::Result<T> {
+        rmp_serde::from_slice(bytes).map_err(invalid)
+    }
+}
+
+#[cfg(feature = "cbor")]
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Cbor;
+
+#[cfg(feature = "cbor")]
+impl Codec for Cbor {
+    fn encode<T: Serialize>(&self, val: &T) -> io::Result<Vec<u8>> {
+        serde_cbor::to_vec(val).map_err(invalid)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> io::Result<T> {
+        serde_cbor::from_slice(bytes).map_err(invalid)
+    }
+}
+
+/// A change to a typed key.
+pub enum TypedEvent<T> {
+    /// The key was created or updated.
+    Put(String, T),
+    Delete(String),
+}
+
+/// A view of an `EtcdSession` that stores values of type `T`, encoded with `C`. Values that fail
+/// to decode surface as `Error::Io` with `ErrorKind::InvalidData`.
+pub struct Typed<T, C> {
+    session: EtcdSession,
+    codec: C,
+    _values: PhantomData<fn() -> T>,
+}
+
+impl<T, C: Clone> Clone for Typed<T, C> {
+    fn clone(&self) -> Typed<T, C> {
+        Typed {
+            session: self.session.clone(),
+            codec: self.codec.clone(),
+            _values: PhantomData,
+        }
+    }
+}
+
+impl<T, C> Typed<T, C>
+where
+    C: Codec + Clone + Send + 'static,
+{
+    pub fn new(session: &EtcdSession, codec: C) -> Typed<T, C> {
+        Typed {
+            session: session.clone(),
+            codec,
+            _values: PhantomData,
+        }
+    }
+
+    pub async fn put(&self, key: &str, val: &T) -> Result<bool>
+    where
+        T: Serialize,
+    {
+        let bytes = self.codec.encode(val)?;
+        self.session.put_bytes(key, &bytes).await
+    }
+
+    pub async fn get(&self, key: &str) -> Result<Option<T>>
+    where
+        T: DeserializeOwned,
+    {
+        let resp = self.session.range_raw(&RangeRequest::new(key)).await?;
+        match resp.kvs.as_ref().and_then(|kvs| kvs.first()) {
+            Some(kv) => decode(&self.codec, kv).map(|(_, val)| Some(val)),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn get_prefix(&self, key: &str) -> Result<Vec<(String, T)>>
+    where
+        T: DeserializeOwned,
+    {
+        let resp = self.session.get_prefix_raw(key).await?;
+        resp.kvs
+            .unwrap_or_default()
+            .iter()
+            .map(|kv| decode(&self.codec, kv))
+            .collect()
+    }
+
+    /// Create a new stream of decoded changes to a key.
+    pub async fn watch(&self, key: &str) -> Result<BoxStream<'static, Result<TypedEvent<T>>>>
+    where
+        T: DeserializeOwned + Send + 'static,
+    {
+        self.watch_with(WatchCreateRequest::new_for_key(key)).await
+    }
+
+    /// Create a new stream of decoded changes to keys starting with `key`.
+    pub async fn watch_pfx(&self, key: &str) -> Result<BoxStream<'static, Result<TypedEvent<T>>>>
+    where
+        T: DeserializeOwned + Send + 'static,
+    {
+        self.watch_with(WatchCreateRequest::new_for_prefix(key))
+            .await
+    }
+
+    /// Create a new stream of decoded events for an arbitrary watch.
+    pub async fn watch_with(
+        &self,
+        create_request: WatchCreateRequest,
+    ) -> Result<BoxStream<'static, Result<TypedEvent<T>>>>
+    where
+        T: DeserializeOwned + Send + 'static,
+    {
+        let codec = self.codec.clone();
+        let watch = self.session.watch_with(create_request).await?;
+        let events = watch.and_then(move |resp| {
+            let events: Result<Vec<TypedEvent<T>>> = resp
+                .events
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|ev| ev.kv.as_ref().map(|kv| (ev, kv)))
+                .map(|(ev, kv)| {
+                    if ev.event_type() == &Some(EventType::DELETE) {
+                        Ok(TypedEvent::Delete(kv.key().unwrap()))
+                    } else {
+                        decode(&codec, kv).map(|(key, val)| TypedEvent::Put(key, val))
+                    }
+                })
+                .collect();
+            futures::future::ready(events.map(|events| stream::iter(events.into_iter().map(Ok))))
+        });
+        Ok(events.try_flatten().boxed())
+    }
+}
+
+fn decode<T: DeserializeOwned, C: Codec>(codec: &C, kv: &KeyValue) -> Result<(String, T)> {
+    let bytes = kv.value_as_u8().unwrap_or_default();
+    codec
+        .decode(&bytes)
+        .map(|val| (kv.key().unwrap(), val))
+        .map_err(Error::Io)
+}
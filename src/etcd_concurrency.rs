@@ -0,0 +1,134 @@
+use super::etcd_actions::{EtcdSession, LeaseKeeper};
+use super::etcd_error::{Error, Result};
+use futures::Future;
+use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A single lease shared by every lock, election and ephemeral key of a process, in the style of
+/// Go's `concurrency.Session`. The lease is kept alive for as long as the `Session` exists, and
+/// once the process dies everything attached to it is released together.
+pub struct Session {
+    client: EtcdSession,
+    keeper: LeaseKeeper,
+    ttl: i64,
+}
+
+impl Session {
+    /// Grant a lease of `ttl` seconds and start keeping it alive.
+    pub async fn new(client: &EtcdSession, ttl: i64) -> Result<Session> {
+        let id = grant_lease(client, ttl).await?;
+        Ok(Session {
+            keeper: client.keep_alive(id, ttl),
+            client: client.clone(),
+            ttl,
+        })
+    }
+
+    pub fn client(&self) -> &EtcdSession {
+        &self.client
+    }
+
+    pub fn lease_id(&self) -> i64 {
+        self.keeper.lease_id()
+    }
+
+    pub fn ttl(&self) -> i64 {
+        self.ttl
+    }
+
+    /// Resolves once the lease is lost, at which point everything attached to it is gone.
+    pub fn done(&self) -> impl Future<Output = ()> + Send + 'static {
+        self.keeper.done()
+    }
+
+    /// Stop refreshing and revoke the lease, releasing everything attached to it.
+    pub async fn close(self) -> Result<()> {
+        self.client.lease_revoke(self.keeper.lease_id()).await
+    }
+}
+
+/// How a recipe obtains the lease its keys are attached to.
+#[derive(Clone, Copy)]
+pub enum LeaseMode {
+    /// Grant a new lease with this TTL for every holder, and revoke it on release.
+    PerHolder(i64),
+    /// Attach keys to the lease of a `Session`, which outlives any single holder.
+    Shared(i64),
+}
+
+impl LeaseMode {
+    pub fn from_session(session: &Session) -> LeaseMode {
+        LeaseMode::Shared(session.lease_id())
+    }
+
+    /// Obtain a lease for a new holder.
+    pub async fn acquire(&self, client: &EtcdSession) -> Result<HeldLease> {
+        match *self {
+            LeaseMode::PerHolder(ttl) => {
+                let id = grant_lease(client, ttl).await?;
+                Ok(HeldLease {
+                    id,
+                    keeper: Some(client.keep_alive(id, ttl)),
+                })
+            }
+            LeaseMode::Shared(id) => Ok(HeldLease { id, keeper: None }),
+        }
+    }
+}
+
+/// The lease backing a single holder of a recipe.
+pub struct HeldLease {
+    id: i64,
+    keeper: Option<LeaseKeeper>,
+}
+
+impl HeldLease {
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    /// Whether this lease belongs to the holder rather than a `Session`.
+    pub fn is_owned(&self) -> bool {
+        self.keeper.is_some()
+    }
+
+    /// Revoke the lease if the holder owns it.
+    pub async fn release(&self, client: &EtcdSession) -> Result<()> {
+        release_lease(client, self.id, self.is_owned()).await
+    }
+}
+
+/// Grant a lease of `ttl` seconds, resolving to its ID. Fails if `etcd` reports an error instead
+/// of granting it, e.g., for an invalid TTL.
+pub(crate) async fn grant_lease(client: &EtcdSession, ttl: i64) -> Result<i64> {
+    let resp = client.lease_grant(ttl).await?;
+    let error = resp.error.as_ref().filter(|error| !error.is_empty());
+    match (resp.id(), error) {
+        (Some(id), None) => Ok(id),
+        (_, error) => Err(Error::Io(io::Error::other(format!(
+            "lease grant failed: {}",
+            error.map_or("no lease ID returned", String::as_str)
+        )))),
+    }
+}
+
+/// Revoke lease `id` if `owned`, otherwise leave it to its `Session`.
+pub async fn release_lease(client: &EtcdSession, id: i64, owned: bool) -> Result<()> {
+    if owned {
+        client.lease_revoke(id).await
+    } else {
+        Ok(())
+    }
+}
+
+/// A key under `prefix` that is unique to this holder, even when holders share a lease.
+pub fn unique_key(prefix: &str, lease: i64) -> String {
+    let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    format!(
+        "{}{:x}-{}{:09}",
+        prefix,
+        lease,
+        since_epoch.as_secs(),
+        since_epoch.subsec_nanos()
+    )
+}
@@ -0,0 +1,127 @@
+use super::etcd_actions::EtcdSession;
+use super::etcd_error::{Error, Result};
+use super::etcd_proto::*;
+use std::io;
+use std::num::NonZeroU32;
+use std::sync::{Arc, Mutex};
+
+/// A cluster-wide counter stored as a decimal string in a single key. Updates are
+/// compare-and-swap transactions on the key's mod revision, retried until they apply.
+pub struct Counter {
+    session: EtcdSession,
+    key: String,
+}
+
+impl Counter {
+    pub fn new(session: &EtcdSession, key: &str) -> Counter {
+        Counter {
+            session: session.clone(),
+            key: String::from(key),
+        }
+    }
+
+    /// Current value, a counter that was never updated reads as zero.
+    pub async fn get(&self) -> Result<i64> {
+        let resp = self
+            .session
+            .range_raw(&RangeRequest::new(&self.key))
+            .await?;
+        Ok(read(&resp)?.0)
+    }
+
+    /// Add `delta`, resolving to the updated value.
+    pub async fn add(&self, delta: i64) -> Result<i64> {
+        loop {
+            let resp = self
+                .session
+                .range_raw(&RangeRequest::new(&self.key))
+                .await?;
+            let (value, rev) = read(&resp)?;
+            let updated = value + delta;
+            // A key that does not exist has a mod revision of zero.
+            let txn = TxnRequest::new(
+                vec![Compare::new_mod_revision(
+                    &self.key,
+                    CompareResult::EQUAL,
+                    rev,
+                )],
+                vec![RequestOp::Put(PutRequest::new(
+                    &self.key,
+                    &updated.to_string(),
+                ))],
+                vec![],
+            );
+            if self.session.txn(&txn).await?.succeeded() {
+                return Ok(updated);
+            }
+        }
+    }
+
+    pub async fn increment(&self) -> Result<i64> {
+        self.add(1).await
+    }
+
+    pub async fn decrement(&self) -> Result<i64> {
+        self.add(-1).await
+    }
+}
+
+/// Value and mod revision of the counter in a `RangeResponse`, failing if the key holds anything
+/// but a number.
+fn read(resp: &RangeResponse) -> Result<(i64, i64)> {
+    let kv = match resp.kvs.as_ref().and_then(|kvs| kvs.first()) {
+        Some(kv) => kv,
+        None => return Ok((0, 0)),
+    };
+    match kv.value().and_then(|value| value.parse::<i64>().ok()) {
+        Some(value) => Ok((value, kv.mod_rev())),
+        None => Err(Error::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "counter {} does not hold a number",
+                kv.key().unwrap_or_default()
+            ),
+        ))),
+    }
+}
+
+/// Hands out unique IDs from a `Counter`, reserving them in blocks so that most IDs need no round
+/// trip. IDs from a single `Sequence` increase within each block, but are not ordered with respect
+/// to IDs handed out by other processes.
+pub struct Sequence {
+    counter: Counter,
+    block_size: i64,
+    /// Next ID to hand out and the last ID of the current block.
+    block: Arc<Mutex<(i64, i64)>>,
+}
+
+impl Sequence {
+    /// Create a sequence backed by `key`, reserving `block_size` IDs at a time.
+    pub fn new(session: &EtcdSession, key: &str, block_size: NonZeroU32) -> Sequence {
+        Sequence {
+            counter: Counter::new(session, key),
+            block_size: i64::from(block_size.get()),
+            block: Arc::new(Mutex::new((1, 0))),
+        }
+    }
+
+    /// Next ID, reserving a new block once the current one is exhausted.
+    pub async fn next(&self) -> Result<i64> {
+        {
+            let mut block = self.block.lock().unwrap();
+            let (next, last) = *block;
+            if next <= last {
+                *block = (next + 1, last);
+                return Ok(next);
+            }
+        }
+        let last = self.counter.add(self.block_size).await?;
+        let first = last - self.block_size + 1;
+        let mut block = self.block.lock().unwrap();
+        // Concurrent calls may each reserve a block, only keep the newest one.
+        if last > block.1 {
+            *block = (first + 1, last);
+        }
+        Ok(first)
+    }
+}
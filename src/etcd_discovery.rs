@@ -0,0 +1,318 @@
+use super::etcd_actions::{EtcdSession, LeaseKeeper};
+use super::etcd_concurrency::{grant_lease, LeaseMode, Session};
+use super::etcd_error::Result;
+use super::etcd_proto::*;
+use futures::channel::oneshot;
+use futures::future;
+use futures::stream::{self, BoxStream};
+use futures::{StreamExt, TryStreamExt};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const SERVICES_PREFIX: &str = "/services/";
+
+/// Registers service instances under `/services/<name>/<id>`, serialised as JSON and attached to
+/// a kept-alive lease so they disappear when the process does.
+pub struct ServiceRegistry {
+    session: EtcdSession,
+    lease: LeaseMode,
+}
+
+impl ServiceRegistry {
+    /// Create a registry where every registration holds its own lease of `ttl` seconds. If that
+    /// lease is ever lost, e.g., after a network partition, the instance is registered again.
+    pub fn new(session: &EtcdSession, ttl: i64) -> ServiceRegistry {
+        ServiceRegistry {
+            session: session.clone(),
+            lease: LeaseMode::PerHolder(ttl),
+        }
+    }
+
+    /// Create a registry whose registrations are attached to the lease of `session`, and hence
+    /// only live as long as it does.
+    pub fn with_session(session: &Session) -> ServiceRegistry {
+        ServiceRegistry {
+            session: session.client().clone(),
+            lease: LeaseMode::from_session(session),
+        }
+    }
+
+    /// Register `instance` as `id` of service `name`, resolving once it is visible to resolvers.
+    pub async fn register<T: Serialize>(
+        &self,
+        name: &str,
+        id: &str,
+        instance: &T,
+    ) -> Result<Registration> {
+        let session = self.session.clone();
+        let key = format!("{}{}/{}", SERVICES_PREFIX, name, id);
+        let value = serde_json::to_string(instance)?;
+        match self.lease {
+            LeaseMode::PerHolder(ttl) => {
+                let keeper = register_once(&session, &key, &value, ttl).await?;
+                let current = Arc::new(AtomicI64::new(keeper.lease_id()));
+                let (stop, stopped) = oneshot::channel::<()>();
+                let (done, finished) = oneshot::channel::<()>();
+                let maintain = maintain(
+                    session.clone(),
+                    key.clone(),
+                    value,
+                    ttl,
+                    current.clone(),
+                    keeper,
+                );
+                session.spawn(async move {
+                    future::select(Box::pin(maintain), stopped).await;
+                    let _ = done.send(());
+                });
+                Ok(Registration {
+                    session,
+                    key,
+                    lease: current,
+                    owned: true,
+                    maintainer: Some(Maintainer { stop, finished }),
+                    deregistered: false,
+                })
+            }
+            LeaseMode::Shared(id) => {
+                session.put_with_lease(&key, &value, id).await?;
+                Ok(Registration {
+                    session,
+                    key,
+                    lease: Arc::new(AtomicI64::new(id)),
+                    owned: false,
+                    maintainer: None,
+                    deregistered: false,
+                })
+            }
+        }
+    }
+}
+
+/// A registered service instance. It is removed when `deregister` is called or this is dropped.
+pub struct Registration {
+    session: EtcdSession,
+    key: String,
+    lease: Arc<AtomicI64>,
+    owned: bool,
+    maintainer: Option<Maintainer>,
+    deregistered: bool,
+}
+
+/// Handle on the task that re-registers an instance whose lease was lost.
+struct Maintainer {
+    stop: oneshot::Sender<()>,
+    finished: oneshot::Receiver<()>,
+}
+
+impl Maintainer {
+    /// Stop the task, resolving once it can no longer register the instance again.
+    async fn stop(self) {
+        drop(self.stop);
+        let _ = self.finished.await;
+    }
+}
+
+impl Registration {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Lease currently backing the registration, this changes whenever it is re-registered.
+    pub fn lease_id(&self) -> i64 {
+        self.lease.load(Ordering::SeqCst)
+    }
+
+    /// Remove the instance, revoking its lease unless it belongs to a `Session`.
+    pub async fn deregister(mut self) -> Result<()> {
+        self.deregistered = true;
+        deregister(
+            &self.session,
+            &self.key,
+            &self.lease,
+            self.owned,
+            self.maintainer.take(),
+        )
+        .await
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        if !self.deregistered {
+            let session = self.session.clone();
+            let key = self.key.clone();
+            let (lease, owned) = (self.lease.clone(), self.owned);
+            let maintainer = self.maintainer.take();
+            self.session.spawn(async move {
+                let _ = deregister(&session, &key, &lease, owned, maintainer).await;
+            });
+        }
+    }
+}
+
+/// Remove the instance once nothing can register it again, so it cannot reappear under a new
+/// lease after being removed.
+async fn deregister(
+    session: &EtcdSession,
+    key: &str,
+    lease: &AtomicI64,
+    owned: bool,
+    maintainer: Option<Maintainer>,
+) -> Result<()> {
+    if let Some(maintainer) = maintainer {
+        maintainer.stop().await;
+    }
+    let revoked = match owned {
+        true => session.lease_revoke(lease.load(Ordering::SeqCst)).await,
+        false => Ok(()),
+    };
+    // The key may be under a lease the task registered and then lost, so delete it either way.
+    session.delete(key).await?;
+    revoked
+}
+
+/// Put `key` under a new lease of `ttl` seconds and keep that lease alive.
+async fn register_once(
+    session: &EtcdSession,
+    key: &str,
+    value: &str,
+    ttl: i64,
+) -> Result<LeaseKeeper> {
+    let id = grant_lease(session, ttl).await?;
+    let keeper = session.keep_alive(id, ttl);
+    session.put_with_lease(key, value, id).await?;
+    Ok(keeper)
+}
+
+/// Register again, with a fresh lease, every time the current lease is lost.
+async fn maintain(
+    session: EtcdSession,
+    key: String,
+    value: String,
+    ttl: i64,
+    current: Arc<AtomicI64>,
+    mut keeper: LeaseKeeper,
+) {
+    loop {
+        keeper.done().await;
+        drop(keeper);
+        keeper = loop {
+            match register_once(&session, &key, &value, ttl).await {
+                Ok(keeper) => break keeper,
+                // `etcd` is unreachable, back off before trying again.
+                Err(_) => tokio::time::sleep(Duration::from_secs(1)).await,
+            }
+        };
+        current.store(keeper.lease_id(), Ordering::SeqCst);
+    }
+}
+
+/// A change to the instances of a service.
+pub enum ServiceEvent<T> {
+    /// An instance was registered, or its registration changed.
+    Added(String, T),
+    Removed(String),
+}
+
+/// Tracks the live instances of a service through a prefix watch.
+pub struct Resolver<T> {
+    session: EtcdSession,
+    prefix: String,
+    endpoints: Arc<Mutex<BTreeMap<String, T>>>,
+}
+
+impl<T: DeserializeOwned + Clone + Send + 'static> Resolver<T> {
+    pub fn new(session: &EtcdSession, name: &str) -> Resolver<T> {
+        Resolver {
+            session: session.clone(),
+            prefix: format!("{}{}/", SERVICES_PREFIX, name),
+            endpoints: Arc::new(Mutex::new(BTreeMap::new())),
+        }
+    }
+
+    /// Instances seen so far, by id. This is kept current as the stream returned by `watch` is
+    /// consumed.
+    pub fn endpoints(&self) -> BTreeMap<String, T> {
+        self.endpoints.lock().unwrap().clone()
+    }
+
+    /// Create a new stream of changes to the service, starting with an `Added` event for every
+    /// instance that is already registered.
+    pub async fn watch(&self) -> Result<BoxStream<'static, Result<ServiceEvent<T>>>> {
+        let prefix = self.prefix.clone();
+        let endpoints = self.endpoints.clone();
+        let resp = self.session.get_prefix_raw(&self.prefix).await?;
+        let rev = resp.header.as_ref().unwrap().rev();
+        let initial: Vec<Result<ServiceEvent<T>>> = resp
+            .kvs
+            .as_ref()
+            .map(|kvs| {
+                kvs.iter()
+                    .filter_map(|kv| decode_event(&prefix, kv, false))
+                    .map(Ok)
+                    .collect()
+            })
+            .unwrap_or_default();
+        let create_request = WatchCreateRequest {
+            start_revision: Some((rev + 1).to_string()),
+            ..WatchCreateRequest::new_for_prefix(&prefix)
+        };
+        let watch = self.session.watch_with(create_request).await?;
+        let changes = watch
+            .map_ok(move |resp| {
+                let events: Vec<Result<ServiceEvent<T>>> = resp
+                    .events
+                    .as_ref()
+                    .map(|events| {
+                        events
+                            .iter()
+                            .filter_map(|ev| {
+                                let deleted = ev.event_type() == &Some(EventType::DELETE);
+                                ev.kv
+                                    .as_ref()
+                                    .and_then(|kv| decode_event(&prefix, kv, deleted))
+                            })
+                            .map(Ok)
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                stream::iter(events)
+            })
+            .try_flatten();
+        Ok(stream::iter(initial)
+            .chain(changes)
+            .inspect_ok(move |event| {
+                let mut endpoints = endpoints.lock().unwrap();
+                match *event {
+                    ServiceEvent::Added(ref id, ref instance) => {
+                        endpoints.insert(id.clone(), instance.clone());
+                    }
+                    ServiceEvent::Removed(ref id) => {
+                        endpoints.remove(id);
+                    }
+                }
+            })
+            .boxed())
+    }
+}
+
+/// Turn a registration into an event, skipping instances that do not decode as `T`.
+fn decode_event<T: DeserializeOwned>(
+    prefix: &str,
+    kv: &KeyValue,
+    deleted: bool,
+) -> Option<ServiceEvent<T>> {
+    let id = String::from(&kv.key().unwrap()[prefix.len()..]);
+    if deleted {
+        Some(ServiceEvent::Removed(id))
+    } else {
+        kv.value()
+            .and_then(|v| serde_json::from_str(&v).ok())
+            .map(|instance| ServiceEvent::Added(id, instance))
+    }
+}
@@ -0,0 +1,126 @@
+use super::etcd_actions::EtcdSession;
+use super::etcd_concurrency::{release_lease, HeldLease, LeaseMode, Session};
+use super::etcd_error::Result;
+use super::etcd_proto::*;
+use futures::future;
+use futures::stream::BoxStream;
+use futures::{StreamExt, TryStreamExt};
+
+/// Leader election built on the `etcd` `v3election` service. Every campaign is tied to a lease,
+/// so a crashed leader loses leadership once the lease expires.
+pub struct Election {
+    session: EtcdSession,
+    name: String,
+    lease: LeaseMode,
+}
+
+impl Election {
+    /// Create a handle for the election `name`, campaigning with leases of `ttl` seconds.
+    pub fn new(session: &EtcdSession, name: &str, ttl: i64) -> Election {
+        Election {
+            session: session.clone(),
+            name: String::from(name),
+            lease: LeaseMode::PerHolder(ttl),
+        }
+    }
+
+    /// Create a handle for the election `name`, campaigning with the lease of `session`.
+    pub fn with_session(session: &Session, name: &str) -> Election {
+        Election {
+            session: session.client().clone(),
+            name: String::from(name),
+            lease: LeaseMode::from_session(session),
+        }
+    }
+
+    /// Campaign announcing `value`, resolving once we are leader.
+    pub async fn campaign(&self, value: &str) -> Result<Leadership> {
+        let lease = self.lease.acquire(&self.session).await?;
+        match self.session.campaign(&self.name, lease.id(), value).await {
+            Ok(resp) => Ok(Leadership {
+                session: self.session.clone(),
+                leader: resp.leader.unwrap(),
+                lease,
+                resigned: false,
+            }),
+            Err(err) => {
+                let _ = lease.release(&self.session).await;
+                Err(err)
+            }
+        }
+    }
+
+    /// Value announced by the current leader. Fails if nobody is leader.
+    pub async fn leader(&self) -> Result<Option<String>> {
+        let resp = self.session.leader(&self.name).await?;
+        Ok(resp.kv.and_then(|kv| kv.value()))
+    }
+
+    /// Create a new stream of the values announced by successive leaders.
+    pub async fn observe(&self) -> Result<BoxStream<'static, Result<String>>> {
+        let stream = self.session.observe(&self.name).await?;
+        Ok(stream
+            .try_filter_map(|resp| future::ok(resp.kv.and_then(|kv| kv.value())))
+            .boxed())
+    }
+}
+
+/// Leadership won through `Election::campaign`. Leadership is given up when `resign` is called or
+/// this is dropped.
+pub struct Leadership {
+    session: EtcdSession,
+    leader: LeaderKey,
+    lease: HeldLease,
+    resigned: bool,
+}
+
+impl Leadership {
+    /// Key identifying this campaign.
+    pub fn key(&self) -> Option<String> {
+        self.leader.key()
+    }
+
+    /// Create revision of the campaign key, this increases with every new leader.
+    pub fn rev(&self) -> i64 {
+        self.leader.rev()
+    }
+
+    pub fn lease_id(&self) -> i64 {
+        self.lease.id()
+    }
+
+    /// Announce a new value while remaining leader.
+    pub async fn proclaim(&self, value: &str) -> Result<()> {
+        self.session.proclaim(&self.leader, value).await
+    }
+
+    /// Give up leadership, and its lease unless it belongs to a `Session`.
+    pub async fn resign(mut self) -> Result<()> {
+        self.resigned = true;
+        resign(
+            &self.session,
+            &self.leader,
+            self.lease.id(),
+            self.lease.is_owned(),
+        )
+        .await
+    }
+}
+
+impl Drop for Leadership {
+    fn drop(&mut self) {
+        if !self.resigned {
+            let session = self.session.clone();
+            let leader = self.leader.clone();
+            let (id, owned) = (self.lease.id(), self.lease.is_owned());
+            self.session.spawn(async move {
+                let _ = resign(&session, &leader, id, owned).await;
+            });
+        }
+    }
+}
+
+async fn resign(session: &EtcdSession, leader: &LeaderKey, lease: i64, owned: bool) -> Result<()> {
+    session.resign(leader).await?;
+    release_lease(session, lease, owned).await
+}
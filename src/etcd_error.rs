@@ -0,0 +1,71 @@
+use hyper::StatusCode;
+use std::error;
+use std::fmt;
+use std::io;
+
+/// Errors returned when talking to `etcd`.
+#[derive(Debug)]
+pub enum Error {
+    /// The request could not be sent, or its response could not be read.
+    Http(Box<dyn error::Error + Send + Sync>),
+    /// `etcd` answered with a status other than `200 OK`.
+    Status(StatusCode),
+    /// A response could not be decoded.
+    Json(serde_json::Error),
+    /// Failures that do not come from the transport, e.g., a value that does not decode.
+    Io(io::Error),
+    /// An operation with a deadline did not finish in time.
+    Timeout,
+    /// A replayed session sent a request its cassette holds no recording of.
+    Unrecorded(String),
+}
+
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Http(ref err) => write!(f, "HTTP error: {}", err),
+            Error::Status(status) => write!(f, "etcd returned {}", status),
+            Error::Json(ref err) => write!(f, "malformed response: {}", err),
+            Error::Io(ref err) => err.fmt(f),
+            Error::Timeout => f.write_str("timed out"),
+            Error::Unrecorded(ref request) => write!(f, "no recorded response for {}", request),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Http(ref err) => Some(&**err),
+            Error::Json(ref err) => Some(err),
+            Error::Io(ref err) => Some(err),
+            Error::Status(_) | Error::Timeout | Error::Unrecorded(_) => None,
+        }
+    }
+}
+
+impl From<hyper::Error> for Error {
+    fn from(err: hyper::Error) -> Error {
+        Error::Http(Box::new(err))
+    }
+}
+
+impl From<hyper_util::client::legacy::Error> for Error {
+    fn from(err: hyper_util::client::legacy::Error) -> Error {
+        Error::Http(Box::new(err))
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Error {
+        Error::Json(err)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
@@ -0,0 +1,332 @@
+//! Dumping a prefix to JSON Lines and loading it back, e.g., to migrate data between clusters.
+//! Every line of a dump is one `Record`:
+//!
+//! ```text
+//! {"key":"app/mode","value":"fast","encoding":"utf8","create_revision":12,"mod_revision":40,"version":3}
+//! ```
+
+use super::etcd_actions::EtcdSession;
+use super::etcd_error::{Error, Result};
+use super::etcd_proto::*;
+use futures::stream::BoxStream;
+use futures::{StreamExt, TryStreamExt};
+use std::io::{self, BufRead, Write};
+
+/// How the key and value of a `Record` are written.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    /// As text, used when both the key and the value are valid UTF-8.
+    Utf8,
+    Base64,
+}
+
+/// A single key in a dump.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Record {
+    pub key: String,
+    pub value: String,
+    pub encoding: Encoding,
+    pub create_revision: i64,
+    pub mod_revision: i64,
+    pub version: i64,
+    /// The lease the key was attached to, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lease: Option<i64>,
+}
+
+impl Record {
+    pub fn from_kv(kv: &KeyValue) -> Record {
+        let key = kv.key_as_u8().unwrap_or_default();
+        let value = kv.value_as_u8().unwrap_or_default();
+        let (key, value, encoding) = match (String::from_utf8(key), String::from_utf8(value)) {
+            (Ok(key), Ok(value)) => (key, value, Encoding::Utf8),
+            (key, value) => (
+                base64::encode(&key.map_or_else(|err| err.into_bytes(), String::into_bytes)),
+                base64::encode(&value.map_or_else(|err| err.into_bytes(), String::into_bytes)),
+                Encoding::Base64,
+            ),
+        };
+        let number = |field: &Option<String>| field.as_ref().map_or(0, |v| v.parse().unwrap_or(0));
+        Record {
+            key,
+            value,
+            encoding,
+            create_revision: kv.create_rev(),
+            mod_revision: kv.mod_rev(),
+            version: number(&kv.version),
+            lease: Some(number(&kv.lease)).filter(|&lease| lease != 0),
+        }
+    }
+
+    pub fn key_bytes(&self) -> Result<Vec<u8>> {
+        self.decode(&self.key)
+    }
+
+    pub fn value_bytes(&self) -> Result<Vec<u8>> {
+        self.decode(&self.value)
+    }
+
+    fn decode(&self, field: &str) -> Result<Vec<u8>> {
+        match self.encoding {
+            Encoding::Utf8 => Ok(field.as_bytes().to_vec()),
+            Encoding::Base64 => base64::decode(field).map_err(|err| invalid(&err.to_string())),
+        }
+    }
+}
+
+/// Read the records of a dump, skipping blank lines.
+pub fn read_records<R: BufRead>(input: R) -> impl Iterator<Item = Result<Record>> {
+    input.lines().filter_map(|line| match line {
+        Ok(ref line) if line.trim().is_empty() => None,
+        Ok(line) => Some(serde_json::from_str(&line).map_err(Error::from)),
+        Err(err) => Some(Err(Error::from(err))),
+    })
+}
+
+/// Outcome of `Export::write_to`.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExportSummary {
+    /// Revision every key was read at.
+    pub revision: i64,
+    pub keys: usize,
+}
+
+/// Reads every key under a prefix as of a single revision, a page at a time.
+#[derive(Clone)]
+pub struct Export {
+    session: EtcdSession,
+    prefix: String,
+    page_size: usize,
+    revision: Option<i64>,
+}
+
+impl Export {
+    pub fn new(session: &EtcdSession, prefix: &str) -> Export {
+        Export {
+            session: session.clone(),
+            prefix: String::from(prefix),
+            page_size: 1000,
+            revision: None,
+        }
+    }
+
+    /// Number of keys fetched per request.
+    pub fn page_size(mut self, page_size: usize) -> Export {
+        self.page_size = page_size;
+        self
+    }
+
+    /// Read the keys as of `revision`, which must not have been compacted, rather than as of the
+    /// start of the export.
+    pub fn revision(mut self, revision: i64) -> Export {
+        self.revision = Some(revision);
+        self
+    }
+
+    /// The exported keys, in key order.
+    pub fn records(&self) -> BoxStream<'static, Result<Record>> {
+        let kvs = match self.revision {
+            Some(rev) => self
+                .session
+                .scan_prefix_at(&self.prefix, self.page_size, rev),
+            None => self.session.scan_prefix(&self.prefix, self.page_size),
+        };
+        kvs.map_ok(|kv| Record::from_kv(&kv)).boxed()
+    }
+
+    /// Write every exported key to `out`, one JSON object per line.
+    pub async fn write_to<W: Write>(&self, mut out: W) -> Result<ExportSummary> {
+        let revision = match self.revision {
+            Some(rev) => rev,
+            None => {
+                let req = RangeRequest {
+                    count_only: Some(true),
+                    ..RangeRequest::new_for_prefix(&self.prefix)
+                };
+                let resp = self.session.range_raw(&req).await?;
+                resp.header.as_ref().map_or(0, ResponseHeader::rev)
+            }
+        };
+        let mut records = self.clone().revision(revision).records();
+        let mut keys = 0;
+        while let Some(record) = records.try_next().await? {
+            serde_json::to_writer(&mut out, &record)?;
+            out.write_all(b"\n")?;
+            keys += 1;
+        }
+        out.flush()?;
+        Ok(ExportSummary { revision, keys })
+    }
+}
+
+/// What `Import` does with keys that already exist.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImportMode {
+    Overwrite,
+    /// Leave existing keys untouched.
+    PutIfAbsent,
+}
+
+/// Outcome of an import. A dry run reports what would have been written.
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ImportSummary {
+    pub imported: usize,
+    /// Keys left alone because they already existed.
+    pub skipped: usize,
+    /// Number of transactions the import was split into.
+    pub batches: usize,
+}
+
+/// Writes records to a cluster in transactions of up to `batch_size` keys. Each batch is applied
+/// atomically, but a failed import may leave earlier batches written.
+pub struct Import {
+    session: EtcdSession,
+    mode: ImportMode,
+    batch_size: usize,
+    dry_run: bool,
+    rewrite: Option<(Vec<u8>, Vec<u8>)>,
+    keep_leases: bool,
+}
+
+impl Import {
+    pub fn new(session: &EtcdSession) -> Import {
+        Import {
+            session: session.clone(),
+            mode: ImportMode::Overwrite,
+            batch_size: 64,
+            dry_run: false,
+            rewrite: None,
+            keep_leases: false,
+        }
+    }
+
+    pub fn mode(mut self, mode: ImportMode) -> Import {
+        self.mode = mode;
+        self
+    }
+
+    /// Keys per transaction, which must stay below the `--max-txn-ops` of the cluster.
+    pub fn batch_size(mut self, batch_size: usize) -> Import {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Only read from the cluster, counting what would be imported or skipped.
+    pub fn dry_run(mut self, dry_run: bool) -> Import {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Replace `from` at the start of every key with `to`. Importing a key that does not start
+    /// with `from` fails.
+    pub fn rewrite_prefix(mut self, from: &str, to: &str) -> Import {
+        self.rewrite = Some((from.as_bytes().to_vec(), to.as_bytes().to_vec()));
+        self
+    }
+
+    /// Attach keys to the lease they were exported with, which must exist in the target cluster.
+    /// By default leases are dropped, since lease IDs do not carry over between clusters.
+    pub fn keep_leases(mut self, keep_leases: bool) -> Import {
+        self.keep_leases = keep_leases;
+        self
+    }
+
+    /// Import the records of a dump written by `Export::write_to`.
+    pub async fn read_from<R: BufRead>(&self, input: R) -> Result<ImportSummary> {
+        self.import(read_records(input)).await
+    }
+
+    pub async fn import<I>(&self, records: I) -> Result<ImportSummary>
+    where
+        I: IntoIterator<Item = Result<Record>>,
+    {
+        let mut summary = ImportSummary::default();
+        let mut batch = Vec::with_capacity(self.batch_size);
+        for record in records {
+            batch.push(self.put_request(&record?)?);
+            if batch.len() == self.batch_size {
+                self.apply(&mut batch, &mut summary).await?;
+            }
+        }
+        if !batch.is_empty() {
+            self.apply(&mut batch, &mut summary).await?;
+        }
+        Ok(summary)
+    }
+
+    fn put_request(&self, record: &Record) -> Result<PutRequest> {
+        let mut key = record.key_bytes()?;
+        if let Some((ref from, ref to)) = self.rewrite {
+            if !key.starts_with(from) {
+                return Err(invalid(&format!(
+                    "key {} does not start with {}",
+                    record.key,
+                    String::from_utf8_lossy(from)
+                )));
+            }
+            key.splice(..from.len(), to.iter().cloned());
+        }
+        Ok(PutRequest {
+            key: Some(base64::encode(&key)),
+            lease: record
+                .lease
+                .filter(|_| self.keep_leases)
+                .map(|lease| lease.to_string()),
+            ..PutRequest::new_bytes("", &record.value_bytes()?)
+        })
+    }
+
+    async fn apply(&self, batch: &mut Vec<PutRequest>, summary: &mut ImportSummary) -> Result<()> {
+        summary.batches += 1;
+        if self.dry_run && self.mode == ImportMode::Overwrite {
+            summary.imported += batch.len();
+            batch.clear();
+            return Ok(());
+        }
+        let ops = batch
+            .drain(..)
+            .map(|put| match (self.dry_run, self.mode) {
+                (true, _) => RequestOp::Range(RangeRequest {
+                    key: put.key,
+                    count_only: Some(true),
+                    ..RangeRequest::new("")
+                }),
+                (false, ImportMode::Overwrite) => RequestOp::Put(put),
+                // A create revision of zero only matches keys that do not exist.
+                (false, ImportMode::PutIfAbsent) => RequestOp::Txn(TxnRequest::new(
+                    vec![Compare {
+                        key: put.key.clone(),
+                        ..Compare::new_create_revision("", CompareResult::EQUAL, 0)
+                    }],
+                    vec![RequestOp::Put(put)],
+                    vec![],
+                )),
+            })
+            .collect();
+        let resp = self
+            .session
+            .txn(&TxnRequest::new(vec![], ops, vec![]))
+            .await?;
+        for op in resp.responses.unwrap_or_default() {
+            let written = match op {
+                ResponseOp::Range(range) => range.count() == 0,
+                ResponseOp::Txn(txn) => txn.succeeded(),
+                _ => true,
+            };
+            if written {
+                summary.imported += 1;
+            } else {
+                summary.skipped += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::Io(io::Error::new(
+        io::ErrorKind::InvalidData,
+        String::from(msg),
+    ))
+}
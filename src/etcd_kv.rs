@@ -0,0 +1,135 @@
+//! The key-value, watch and lease services as traits, so code can be written against either a
+//! live `EtcdSession` or the in-memory `MemoryStore`. Every method mirrors the `EtcdSession`
+//! method of the same name.
+
+use super::etcd_actions::EtcdSession;
+use super::etcd_error::Result;
+use super::etcd_proto::*;
+use super::etcd_range::{RangeOptions, RangeResult};
+use futures::future::BoxFuture;
+use futures::stream::BoxStream;
+use futures::FutureExt;
+
+pub trait Kv: Send + Sync {
+    fn put<'a>(&'a self, key: &'a str, val: &'a str) -> BoxFuture<'a, Result<bool>>;
+
+    /// Put a key that is deleted when `lease` expires or is revoked.
+    fn put_with_lease<'a>(
+        &'a self,
+        key: &'a str,
+        val: &'a str,
+        lease: i64,
+    ) -> BoxFuture<'a, Result<bool>>;
+
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<String>>>;
+
+    fn get_prefix<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Vec<(String, String)>>>;
+
+    /// Read the keys selected by `opts`.
+    fn range<'a>(&'a self, opts: &'a RangeOptions) -> BoxFuture<'a, Result<RangeResult>>;
+
+    /// Delete a key, returning the number of keys deleted.
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<usize>>;
+
+    /// Delete every key starting with `prefix`, returning the number of keys deleted.
+    fn delete_prefix<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<usize>>;
+
+    fn txn<'a>(&'a self, req: &'a TxnRequest) -> BoxFuture<'a, Result<TxnResponse>>;
+}
+
+pub trait Watch: Send + Sync {
+    /// Start an arbitrary watch, e.g., one starting at a past revision.
+    fn watch_with(
+        &self,
+        create_request: WatchCreateRequest,
+    ) -> BoxFuture<'_, Result<BoxStream<'static, Result<WatchResponse>>>>;
+
+    /// Create a new stream that reports changes to a key.
+    fn watch<'a>(
+        &'a self,
+        key: &'a str,
+    ) -> BoxFuture<'a, Result<BoxStream<'static, Result<WatchResponse>>>> {
+        self.watch_with(WatchCreateRequest::new_for_key(key))
+    }
+
+    /// Create a new stream that reports changes to keys starting with `prefix`.
+    fn watch_pfx<'a>(
+        &'a self,
+        prefix: &'a str,
+    ) -> BoxFuture<'a, Result<BoxStream<'static, Result<WatchResponse>>>> {
+        self.watch_with(WatchCreateRequest::new_for_prefix(prefix))
+    }
+}
+
+pub trait Lease: Send + Sync {
+    /// Grant a lease that expires after `ttl` seconds unless kept alive.
+    fn lease_grant(&self, ttl: i64) -> BoxFuture<'_, Result<LeaseGrantResponse>>;
+
+    /// Revoke a lease, deleting every key attached to it.
+    fn lease_revoke(&self, id: i64) -> BoxFuture<'_, Result<()>>;
+
+    /// Refresh a lease once.
+    fn lease_keep_alive(&self, id: i64) -> BoxFuture<'_, Result<LeaseKeepAliveResponse>>;
+}
+
+impl Kv for EtcdSession {
+    fn put<'a>(&'a self, key: &'a str, val: &'a str) -> BoxFuture<'a, Result<bool>> {
+        EtcdSession::put(self, key, val).boxed()
+    }
+
+    fn put_with_lease<'a>(
+        &'a self,
+        key: &'a str,
+        val: &'a str,
+        lease: i64,
+    ) -> BoxFuture<'a, Result<bool>> {
+        EtcdSession::put_with_lease(self, key, val, lease).boxed()
+    }
+
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<String>>> {
+        EtcdSession::get(self, key).boxed()
+    }
+
+    fn get_prefix<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Vec<(String, String)>>> {
+        EtcdSession::get_prefix(self, key).boxed()
+    }
+
+    fn range<'a>(&'a self, opts: &'a RangeOptions) -> BoxFuture<'a, Result<RangeResult>> {
+        EtcdSession::range(self, opts).boxed()
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<usize>> {
+        EtcdSession::delete(self, key).boxed()
+    }
+
+    fn delete_prefix<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<usize>> {
+        EtcdSession::delete_prefix(self, prefix).boxed()
+    }
+
+    fn txn<'a>(&'a self, req: &'a TxnRequest) -> BoxFuture<'a, Result<TxnResponse>> {
+        EtcdSession::txn(self, req).boxed()
+    }
+}
+
+impl Watch for EtcdSession {
+    fn watch_with(
+        &self,
+        create_request: WatchCreateRequest,
+    ) -> BoxFuture<'_, Result<BoxStream<'static, Result<WatchResponse>>>> {
+        EtcdSession::watch_with(self, create_request).boxed()
+    }
+}
+
+impl Lease for EtcdSession {
+    fn lease_grant(&self, ttl: i64) -> BoxFuture<'_, Result<LeaseGrantResponse>> {
+        EtcdSession::lease_grant(self, ttl).boxed()
+    }
+
+    fn lease_revoke(&self, id: i64) -> BoxFuture<'_, Result<()>> {
+        EtcdSession::lease_revoke(self, id).boxed()
+    }
+
+    fn lease_keep_alive(&self, id: i64) -> BoxFuture<'_, Result<LeaseKeepAliveResponse>> {
+        EtcdSession::lease_keep_alive(self, id).boxed()
+    }
+}
@@ -0,0 +1,391 @@
+use super::etcd_actions::{wait_deletion, EtcdSession};
+use super::etcd_concurrency::{release_lease, unique_key, HeldLease, LeaseMode, Session};
+use super::etcd_error::{Error, Result};
+use super::etcd_kv::{Kv, Watch};
+use super::etcd_proto::*;
+use super::etcd_range::RangeOptions;
+use std::io;
+
+/// A cross-process mutex built on the `etcd` `v3lock` service. Every acquired lock is tied to a
+/// lease, so a crashed holder releases the lock once the lease expires.
+pub struct Mutex {
+    session: EtcdSession,
+    lease: LeaseMode,
+}
+
+impl Mutex {
+    /// Create a mutex whose locks are each held with their own lease of `ttl` seconds.
+    pub fn new(session: &EtcdSession, ttl: i64) -> Mutex {
+        Mutex {
+            session: session.clone(),
+            lease: LeaseMode::PerHolder(ttl),
+        }
+    }
+
+    /// Create a mutex whose locks are held with the lease of `session`.
+    pub fn with_session(session: &Session) -> Mutex {
+        Mutex {
+            session: session.client().clone(),
+            lease: LeaseMode::from_session(session),
+        }
+    }
+
+    /// Acquire the lock `name`, resolving once it is held.
+    pub async fn lock(&self, name: &str) -> Result<MutexGuard> {
+        let lease = self.lease.acquire(&self.session).await?;
+        match self.session.lock(name, lease.id()).await {
+            Ok(resp) => Ok(MutexGuard {
+                session: self.session.clone(),
+                key: resp.key().unwrap(),
+                lease,
+                released: false,
+            }),
+            Err(err) => {
+                let _ = lease.release(&self.session).await;
+                Err(err)
+            }
+        }
+    }
+}
+
+/// A held lock. The lock is released when `unlock` is called or the guard is dropped.
+pub struct MutexGuard {
+    session: EtcdSession,
+    key: String,
+    lease: HeldLease,
+    released: bool,
+}
+
+impl MutexGuard {
+    /// Key owning the lock, this only exists in `etcd` while the lock is held.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn lease_id(&self) -> i64 {
+        self.lease.id()
+    }
+
+    /// Release the lock, and its lease unless it belongs to a `Session`.
+    pub async fn unlock(mut self) -> Result<()> {
+        self.released = true;
+        unlock(
+            &self.session,
+            &self.key,
+            self.lease.id(),
+            self.lease.is_owned(),
+        )
+        .await
+    }
+}
+
+impl Drop for MutexGuard {
+    fn drop(&mut self) {
+        if !self.released {
+            let session = self.session.clone();
+            let key = self.key.clone();
+            let (id, owned) = (self.lease.id(), self.lease.is_owned());
+            self.session.spawn(async move {
+                let _ = unlock(&session, &key, id, owned).await;
+            });
+        }
+    }
+}
+
+async fn unlock(session: &EtcdSession, key: &str, lease: i64, owned: bool) -> Result<()> {
+    session.unlock(key).await?;
+    release_lease(session, lease, owned).await
+}
+
+/// Client-side lock recipe in the style of Go's `clientv3/concurrency.Mutex`. Waiters create
+/// lease-attached keys under a common prefix, the one with the lowest create revision holds the
+/// lock, and every other waiter watches only its immediate predecessor. Unlike `Mutex` this only
+/// needs the KV, watch and lease services, so it also works with gateways lacking `v3lock`.
+pub struct Lock {
+    session: EtcdSession,
+    prefix: String,
+    lease: LeaseMode,
+}
+
+impl Lock {
+    /// Create a lock whose waiters live under `prefix`, each attached to its own lease of `ttl`
+    /// seconds.
+    pub fn new(session: &EtcdSession, prefix: &str, ttl: i64) -> Lock {
+        Lock {
+            session: session.clone(),
+            prefix: format!("{}/", prefix.trim_end_matches('/')),
+            lease: LeaseMode::PerHolder(ttl),
+        }
+    }
+
+    /// Create a lock whose waiters live under `prefix`, attached to the lease of `session`.
+    pub fn with_session(session: &Session, prefix: &str) -> Lock {
+        Lock {
+            session: session.client().clone(),
+            prefix: format!("{}/", prefix.trim_end_matches('/')),
+            lease: LeaseMode::from_session(session),
+        }
+    }
+
+    /// Acquire the lock, resolving once every earlier waiter has released it.
+    pub async fn lock(&self) -> Result<LockGuard> {
+        let lease = self.lease.acquire(&self.session).await?;
+        // Holders sharing a session lease still need keys of their own.
+        let key = unique_key(&self.prefix, lease.id());
+        let acquired = async {
+            let rev = claim_key(&self.session, &key, lease.id()).await?;
+            wait_deletes(&self.session, &self.prefix, rev - 1).await?;
+            Ok(rev)
+        };
+        match acquired.await {
+            Ok(rev) => Ok(LockGuard {
+                session: self.session.clone(),
+                key,
+                revision: rev,
+                lease,
+                released: false,
+            }),
+            Err(err) => {
+                let _ = abandon(&self.session, &key, lease.id(), lease.is_owned()).await;
+                Err(err)
+            }
+        }
+    }
+}
+
+/// A lock held through `Lock`. The lock is released when `unlock` is called or the guard is
+/// dropped.
+pub struct LockGuard {
+    session: EtcdSession,
+    key: String,
+    revision: i64,
+    lease: HeldLease,
+    released: bool,
+}
+
+impl LockGuard {
+    /// Key owning the lock.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Create revision of the owning key. This increases every time the lock changes hands, so
+    /// downstream storage can use it to reject writes from stale holders.
+    pub fn fencing_token(&self) -> i64 {
+        self.revision
+    }
+
+    pub fn lease_id(&self) -> i64 {
+        self.lease.id()
+    }
+
+    /// Release the lock, and its lease unless it belongs to a `Session`.
+    pub async fn unlock(mut self) -> Result<()> {
+        self.released = true;
+        abandon(
+            &self.session,
+            &self.key,
+            self.lease.id(),
+            self.lease.is_owned(),
+        )
+        .await
+    }
+}
+
+impl Drop for LockGuard {
+    fn drop(&mut self) {
+        if !self.released {
+            spawn_abandon(&self.session, &self.key, &self.lease);
+        }
+    }
+}
+
+/// A distributed read-write lock. Readers and writers queue lease-attached keys under `read/` and
+/// `write/` sub-prefixes, ordered by create revision. A reader waits only for earlier writers and
+/// a writer for every earlier waiter, each watching just its closest conflicting predecessor.
+pub struct RwLock {
+    session: EtcdSession,
+    prefix: String,
+    lease: LeaseMode,
+}
+
+impl RwLock {
+    /// Create a lock whose waiters live under `prefix`, each attached to its own lease of `ttl`
+    /// seconds.
+    pub fn new(session: &EtcdSession, prefix: &str, ttl: i64) -> RwLock {
+        RwLock {
+            session: session.clone(),
+            prefix: format!("{}/", prefix.trim_end_matches('/')),
+            lease: LeaseMode::PerHolder(ttl),
+        }
+    }
+
+    /// Create a lock whose waiters live under `prefix`, attached to the lease of `session`.
+    pub fn with_session(session: &Session, prefix: &str) -> RwLock {
+        RwLock {
+            session: session.client().clone(),
+            prefix: format!("{}/", prefix.trim_end_matches('/')),
+            lease: LeaseMode::from_session(session),
+        }
+    }
+
+    /// Acquire shared access, resolving once every earlier writer has released the lock.
+    pub async fn read(&self) -> Result<RwLockGuard> {
+        let conflicts = format!("{}write/", self.prefix);
+        self.acquire(&format!("{}read/", self.prefix), &conflicts, false)
+            .await
+    }
+
+    /// Acquire exclusive access, resolving once every earlier reader and writer has released the
+    /// lock.
+    pub async fn write(&self) -> Result<RwLockGuard> {
+        self.acquire(&format!("{}write/", self.prefix), &self.prefix, true)
+            .await
+    }
+
+    async fn acquire(&self, prefix: &str, conflicts: &str, exclusive: bool) -> Result<RwLockGuard> {
+        let lease = self.lease.acquire(&self.session).await?;
+        let key = unique_key(prefix, lease.id());
+        let acquired = async {
+            let rev = claim_key(&self.session, &key, lease.id()).await?;
+            wait_deletes(&self.session, conflicts, rev - 1).await
+        };
+        match acquired.await {
+            Ok(()) => Ok(RwLockGuard {
+                session: self.session.clone(),
+                key,
+                exclusive,
+                lease,
+                released: false,
+            }),
+            Err(err) => {
+                let _ = abandon(&self.session, &key, lease.id(), lease.is_owned()).await;
+                Err(err)
+            }
+        }
+    }
+}
+
+/// A held read or write lock. The lock is released when `unlock` is called or the guard is dropped.
+pub struct RwLockGuard {
+    session: EtcdSession,
+    key: String,
+    exclusive: bool,
+    lease: HeldLease,
+    released: bool,
+}
+
+impl RwLockGuard {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Whether this guard was acquired through `write`.
+    pub fn is_exclusive(&self) -> bool {
+        self.exclusive
+    }
+
+    pub fn lease_id(&self) -> i64 {
+        self.lease.id()
+    }
+
+    /// Release the lock, and its lease unless it belongs to a `Session`.
+    pub async fn unlock(mut self) -> Result<()> {
+        self.released = true;
+        abandon(
+            &self.session,
+            &self.key,
+            self.lease.id(),
+            self.lease.is_owned(),
+        )
+        .await
+    }
+}
+
+impl Drop for RwLockGuard {
+    fn drop(&mut self) {
+        if !self.released {
+            spawn_abandon(&self.session, &self.key, &self.lease);
+        }
+    }
+}
+
+/// Delete a waiter's `key` and release its lease, letting later waiters through.
+pub(crate) async fn abandon(
+    session: &EtcdSession,
+    key: &str,
+    lease: i64,
+    owned: bool,
+) -> Result<()> {
+    session.delete(key).await?;
+    release_lease(session, lease, owned).await
+}
+
+/// `abandon` in the background, for guards that are dropped without being released.
+pub(crate) fn spawn_abandon(session: &EtcdSession, key: &str, lease: &HeldLease) {
+    let background = session.clone();
+    let key = String::from(key);
+    let (id, owned) = (lease.id(), lease.is_owned());
+    session.spawn(async move {
+        let _ = abandon(&background, &key, id, owned).await;
+    });
+}
+
+/// Create `key` attached to `lease` unless it already exists, resolving to its create revision.
+pub(crate) async fn claim_key<S: Kv + ?Sized>(store: &S, key: &str, lease: i64) -> Result<i64> {
+    claim_key_with_value(store, key, "", lease).await
+}
+
+/// Like `claim_key`, storing `value` in the key if it is created.
+pub(crate) async fn claim_key_with_value<S: Kv + ?Sized>(
+    store: &S,
+    key: &str,
+    value: &str,
+    lease: i64,
+) -> Result<i64> {
+    let txn = TxnRequest::new(
+        vec![Compare::new_create_revision(key, CompareResult::EQUAL, 0)],
+        vec![RequestOp::Put(PutRequest::new_with_lease(
+            key, value, lease,
+        ))],
+        vec![RequestOp::Range(RangeRequest::new(key))],
+    );
+    let resp = store.txn(&txn).await?;
+    if resp.succeeded() {
+        return Ok(resp.header.as_ref().unwrap().rev());
+    }
+    let existing = match resp.responses.and_then(|ops| ops.into_iter().next()) {
+        Some(ResponseOp::Range(range)) => range.kvs.and_then(|kvs| kvs.into_iter().next()),
+        _ => None,
+    };
+    existing.map(|kv| kv.create_rev()).ok_or_else(|| {
+        Error::Io(io::Error::other(format!(
+            "claim of {} failed without the key existing",
+            key
+        )))
+    })
+}
+
+/// Resolves once every key under `prefix` created at or before `max_rev` has been deleted.
+pub(crate) async fn wait_deletes<S: Kv + Watch + ?Sized>(
+    store: &S,
+    prefix: &str,
+    max_rev: i64,
+) -> Result<()> {
+    loop {
+        let opts = RangeOptions::prefix(prefix)
+            .sort(SortOrder::DESCEND, SortTarget::CREATE)
+            .max_create_revision(max_rev)
+            .limit(1);
+        let result = store.range(&opts).await?;
+        match result.kvs.first() {
+            // Wait for the closest predecessor only, then check again since earlier waiters may
+            // have given up in the meantime.
+            Some(kv) => {
+                let key = WatchCreateRequest::new_for_key(&kv.key().unwrap());
+                wait_deletion(store, key, result.rev()).await?
+            }
+            None => return Ok(()),
+        }
+    }
+}
@@ -0,0 +1,726 @@
+//! An in-memory stand-in for `etcd` implementing `Kv`, `Watch` and `Lease`, so logic written
+//! against those traits can be unit tested without a live cluster. It keeps every revision of
+//! every key like `etcd` does, so reads at past revisions, transactions comparing revisions and
+//! watches replaying history all behave as they would against the real store. Leases expire
+//! against a manual clock which only moves when `MemoryStore::advance` is called.
+
+use super::etcd_error::{Error, Result};
+use super::etcd_kv::{Kv, Lease, Watch};
+use super::etcd_proto::*;
+use super::etcd_range::{RangeOptions, RangeResult};
+use futures::channel::mpsc;
+use futures::future::{self, BoxFuture};
+use futures::stream::BoxStream;
+use futures::{FutureExt, StreamExt};
+use hyper::StatusCode;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+/// An in-memory `etcd` store. Clones share the same data, like clones of an `EtcdSession` talk to
+/// the same cluster.
+#[derive(Clone)]
+pub struct MemoryStore {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    rev: i64,
+    now: Duration,
+    /// Every revision of every key, oldest first.
+    keys: BTreeMap<Vec<u8>, Vec<Record>>,
+    /// Whether the write in progress has changed anything, and so needs a new revision.
+    dirty: bool,
+    leases: BTreeMap<i64, LeaseState>,
+    last_lease: i64,
+    watchers: Vec<Watcher>,
+    last_watch: i64,
+}
+
+/// A key as of revision `rev`, `None` if it was deleted at that revision.
+struct Record {
+    rev: i64,
+    kv: Option<KeyValue>,
+}
+
+struct LeaseState {
+    ttl: i64,
+    deadline: Duration,
+    keys: BTreeSet<Vec<u8>>,
+}
+
+struct Watcher {
+    id: i64,
+    span: Span,
+    no_put: bool,
+    no_delete: bool,
+    prev_kv: bool,
+    sender: mpsc::UnboundedSender<Result<WatchResponse>>,
+}
+
+/// Keys selected by a `key` and `range_end` pair. An `end` of `None` selects `start` only, an
+/// empty `end` every key from `start` onwards.
+struct Span {
+    start: Vec<u8>,
+    end: Option<Vec<u8>>,
+}
+
+impl Span {
+    fn new(key: &Option<String>, range_end: &Option<String>) -> Span {
+        Span {
+            start: decode(key),
+            // `etcd` uses a range end of "\0" to mean "every key from `key` onwards".
+            end: range_end.as_ref().map(|end| match base64::decode(end) {
+                Ok(ref end) if end[..] == [0] => vec![],
+                Ok(end) => end,
+                Err(_) => vec![],
+            }),
+        }
+    }
+
+    fn contains(&self, key: &[u8]) -> bool {
+        match self.end {
+            None => key == &self.start[..],
+            Some(ref end) => key >= &self.start[..] && (end.is_empty() || key < &end[..]),
+        }
+    }
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        // Like `etcd`, an empty store is at revision 1 and the first write creates revision 2.
+        let state = State {
+            rev: 1,
+            ..State::default()
+        };
+        MemoryStore {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    /// Current store revision, this increases with every write.
+    pub fn revision(&self) -> i64 {
+        self.lock().rev
+    }
+
+    /// Time elapsed on the store's clock.
+    pub fn now(&self) -> Duration {
+        self.lock().now
+    }
+
+    /// Move the store's clock forward by `by`, expiring every lease that is not kept alive
+    /// in time.
+    pub fn advance(&self, by: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.now += by;
+        state.expire();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        let mut state = self.state.lock().unwrap();
+        state.expire();
+        state
+    }
+
+    /// Apply `op` at the next revision, which only becomes current if `op` changed anything.
+    /// Resolves to the output of `op` and the store revision after it. If `op` fails, whatever
+    /// it changed is undone, so a failed transaction leaves the store untouched.
+    fn write<T, F>(&self, op: F) -> Result<(T, i64)>
+    where
+        F: FnOnce(&mut State, i64) -> Result<T>,
+    {
+        let mut state = self.lock();
+        let rev = state.rev + 1;
+        match op(&mut state, rev) {
+            Ok(out) => Ok((out, state.commit(rev))),
+            Err(err) => {
+                state.rollback(rev);
+                Err(err)
+            }
+        }
+    }
+
+    /// Issue an arbitrary `RangeRequest`.
+    pub fn range_raw(&self, req: &RangeRequest) -> Result<RangeResponse> {
+        let state = self.lock();
+        let mut resp = state.range(req)?;
+        resp.header = Some(header(state.rev));
+        Ok(resp)
+    }
+
+    /// Issue an arbitrary `PutRequest`.
+    pub fn put_raw(&self, req: &PutRequest) -> Result<PutResponse> {
+        let (mut resp, rev) = self.write(|state, rev| state.put(rev, req))?;
+        resp.header = Some(header(rev));
+        Ok(resp)
+    }
+
+    /// Issue an arbitrary `DeleteRangeRequest`.
+    pub fn delete_range_raw(&self, req: &DeleteRangeRequest) -> Result<DeleteRangeResponse> {
+        let (mut resp, rev) = self.write(|state, rev| Ok(state.delete_range(rev, req)))?;
+        resp.header = Some(header(rev));
+        Ok(resp)
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> MemoryStore {
+        MemoryStore::new()
+    }
+}
+
+impl State {
+    /// Make `rev` the current revision if the write in progress changed anything, notifying
+    /// watchers. Returns the current revision.
+    fn commit(&mut self, rev: i64) -> i64 {
+        if self.dirty {
+            self.dirty = false;
+            self.rev = rev;
+            self.notify(rev);
+        }
+        self.rev
+    }
+
+    /// Latest value of every live key in `span`, in key order.
+    fn live<'a>(&'a self, span: &'a Span) -> impl Iterator<Item = &'a KeyValue> + 'a {
+        self.keys
+            .iter()
+            .filter(move |(key, _)| span.contains(key))
+            .filter_map(|(_, records)| records.last().and_then(|record| record.kv.as_ref()))
+    }
+
+    /// Latest value of `key`, if it exists.
+    fn current(&self, key: &[u8]) -> Option<&KeyValue> {
+        self.keys
+            .get(key)
+            .and_then(|records| records.last())
+            .and_then(|record| record.kv.as_ref())
+    }
+
+    fn range(&self, req: &RangeRequest) -> Result<RangeResponse> {
+        let rev = number(&req.revision);
+        // Like `etcd`, reject ranges without a key; "\0" is the way to start from the first key.
+        if rev > self.rev || decode(&req.key).is_empty() {
+            return Err(Error::Status(StatusCode::BAD_REQUEST));
+        }
+        let span = Span::new(&req.key, &req.range_end);
+        let (min_mod, max_mod) = (number(&req.min_mod_revision), number(&req.max_mod_revision));
+        let (min_create, max_create) = (
+            number(&req.min_create_revision),
+            number(&req.max_create_revision),
+        );
+        let mut kvs: Vec<KeyValue> = self
+            .keys
+            .iter()
+            .filter(|(key, _)| span.contains(key))
+            .filter_map(|(_, records)| {
+                // Reads without a revision see writes made earlier in the same transaction.
+                let record = if rev > 0 {
+                    records.iter().rev().find(|record| record.rev <= rev)
+                } else {
+                    records.last()
+                };
+                record.and_then(|record| record.kv.as_ref())
+            })
+            .filter(|kv| {
+                (min_mod == 0 || kv.mod_rev() >= min_mod)
+                    && (max_mod == 0 || kv.mod_rev() <= max_mod)
+                    && (min_create == 0 || kv.create_rev() >= min_create)
+                    && (max_create == 0 || kv.create_rev() <= max_create)
+            })
+            .cloned()
+            .collect();
+
+        let target = req.sort_target.unwrap_or(SortTarget::KEY);
+        match req.sort_order.unwrap_or(SortOrder::NONE) {
+            SortOrder::NONE if target == SortTarget::KEY => {}
+            SortOrder::NONE | SortOrder::ASCEND => kvs.sort_by(|a, b| sort_key(target, a, b)),
+            SortOrder::DESCEND => kvs.sort_by(|a, b| sort_key(target, b, a)),
+        }
+
+        let count = kvs.len();
+        let limit = number(&req.limit) as usize;
+        let more = limit > 0 && count > limit;
+        if more {
+            kvs.truncate(limit);
+        }
+        if req.count_only == Some(true) {
+            kvs.clear();
+        } else if req.keys_only == Some(true) {
+            kvs = kvs.iter().map(without_value).collect();
+        }
+        Ok(RangeResponse::new(kvs, more, count))
+    }
+
+    /// Fail the way `etcd` would if `req` cannot be applied, before anything is changed.
+    fn check_put(&self, req: &PutRequest) -> Result<()> {
+        let prev = self.current(&decode(&req.key));
+        if prev.is_none() && (req.ignore_value == Some(true) || req.ignore_lease == Some(true)) {
+            return Err(Error::Status(StatusCode::BAD_REQUEST));
+        }
+        let lease = number(&req.lease);
+        if req.ignore_lease != Some(true) && lease != 0 && !self.leases.contains_key(&lease) {
+            return Err(Error::Status(StatusCode::NOT_FOUND));
+        }
+        Ok(())
+    }
+
+    fn put(&mut self, rev: i64, req: &PutRequest) -> Result<PutResponse> {
+        self.check_put(req)?;
+        let key = decode(&req.key);
+        let prev = self.current(&key).cloned();
+        let value = match (req.ignore_value, prev.as_ref()) {
+            (Some(true), Some(prev)) => prev.value_as_u8().unwrap_or_default(),
+            _ => decode(&req.value),
+        };
+        let lease = match (req.ignore_lease, prev.as_ref()) {
+            (Some(true), Some(prev)) => lease_of(prev),
+            _ => number(&req.lease),
+        };
+        let (create_rev, version) = match prev {
+            Some(ref prev) => (prev.create_rev(), version_of(prev) + 1),
+            None => (rev, 1),
+        };
+
+        let mut kv = KeyValue::default();
+        kv.set_key(&key);
+        if !value.is_empty() {
+            kv.set_value(&value);
+        }
+        kv.create_revision = Some(create_rev.to_string());
+        kv.mod_revision = Some(rev.to_string());
+        kv.version = Some(version.to_string());
+        if lease != 0 {
+            kv.lease = Some(lease.to_string());
+        }
+
+        self.detach(&key, prev.as_ref());
+        if let Some(attached) = self.leases.get_mut(&lease) {
+            attached.keys.insert(key.clone());
+        }
+        self.record(key, rev, Some(kv));
+        Ok(PutResponse {
+            header: None,
+            prev_kv: prev.filter(|_| req.prev_kv == Some(true)),
+        })
+    }
+
+    fn delete_range(&mut self, rev: i64, req: &DeleteRangeRequest) -> DeleteRangeResponse {
+        let deleted: Vec<KeyValue> = self
+            .live(&Span::new(&req.key, &req.range_end))
+            .cloned()
+            .collect();
+        for kv in &deleted {
+            let key = kv.key_as_u8().unwrap();
+            self.detach(&key, Some(kv));
+            self.record(key, rev, None);
+        }
+        let count = deleted.len();
+        DeleteRangeResponse::new(count, Some(deleted).filter(|_| req.prev_kv == Some(true)))
+    }
+
+    fn txn(&mut self, rev: i64, req: &TxnRequest) -> Result<TxnResponse> {
+        let succeeded = req
+            .compare
+            .iter()
+            .flatten()
+            .all(|compare| self.compare(compare));
+        let ops = if succeeded {
+            &req.success
+        } else {
+            &req.failure
+        };
+        let ops = ops.as_ref().map_or(&[][..], |ops| &ops[..]);
+        let mut responses = Vec::with_capacity(ops.len());
+        for op in ops {
+            responses.push(match *op {
+                RequestOp::Range(ref req) => ResponseOp::Range(self.range(req)?),
+                RequestOp::Put(ref req) => ResponseOp::Put(self.put(rev, req)?),
+                RequestOp::DeleteRange(ref req) => {
+                    ResponseOp::DeleteRange(self.delete_range(rev, req))
+                }
+                RequestOp::Txn(ref req) => ResponseOp::Txn(self.txn(rev, req)?),
+            });
+        }
+        Ok(TxnResponse::new(succeeded, responses))
+    }
+
+    fn compare(&self, compare: &Compare) -> bool {
+        let span = Span::new(&compare.key, &compare.range_end);
+        let kvs: Vec<&KeyValue> = self.live(&span).collect();
+        if kvs.is_empty() {
+            // Missing keys compare as if every attribute were zero, but never match on value.
+            return match compare.target_union {
+                CompareTargetUnion::Value(_) => false,
+                _ => compare_kv(compare, &KeyValue::default()),
+            };
+        }
+        kvs.into_iter().all(|kv| compare_kv(compare, kv))
+    }
+
+    fn grant(&mut self, ttl: i64) -> LeaseGrantResponse {
+        self.last_lease += 1;
+        let lease = LeaseState {
+            ttl,
+            deadline: self.now + Duration::from_secs(ttl.max(0) as u64),
+            keys: BTreeSet::new(),
+        };
+        self.leases.insert(self.last_lease, lease);
+        LeaseGrantResponse::new(self.last_lease, ttl)
+    }
+
+    /// Remove a lease and delete every key attached to it, all at a single revision.
+    fn revoke(&mut self, id: i64) -> Result<()> {
+        let lease = match self.leases.remove(&id) {
+            Some(lease) => lease,
+            None => return Err(Error::Status(StatusCode::NOT_FOUND)),
+        };
+        let rev = self.rev + 1;
+        for key in lease.keys {
+            if self.keys[&key]
+                .last()
+                .is_some_and(|record| record.kv.is_some())
+            {
+                self.record(key, rev, None);
+            }
+        }
+        self.commit(rev);
+        Ok(())
+    }
+
+    fn keep_alive(&mut self, id: i64) -> LeaseKeepAliveResponse {
+        match self.leases.get_mut(&id) {
+            Some(lease) => {
+                lease.deadline = self.now + Duration::from_secs(lease.ttl.max(0) as u64);
+                LeaseKeepAliveResponse::new(id, lease.ttl)
+            }
+            // `etcd` reports a TTL of zero for leases that no longer exist.
+            None => LeaseKeepAliveResponse::new(id, 0),
+        }
+    }
+
+    fn expire(&mut self) {
+        let expired: Vec<i64> = self
+            .leases
+            .iter()
+            .filter(|(_, lease)| lease.deadline <= self.now)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in expired {
+            let _ = self.revoke(id);
+        }
+    }
+
+    fn detach(&mut self, key: &[u8], kv: Option<&KeyValue>) {
+        let lease = kv.map_or(0, lease_of);
+        if let Some(attached) = self.leases.get_mut(&lease) {
+            attached.keys.remove(key);
+        }
+    }
+
+    /// Drop every record made at the uncommitted revision `rev`, and attach the keys involved
+    /// to their leases as of the revision before.
+    fn rollback(&mut self, rev: i64) {
+        let mut touched = Vec::new();
+        for (key, records) in self.keys.iter_mut() {
+            if records.last().is_some_and(|record| record.rev == rev) {
+                records.retain(|record| record.rev != rev);
+                touched.push(key.clone());
+            }
+        }
+        self.keys.retain(|_, records| !records.is_empty());
+        for key in touched {
+            for lease in self.leases.values_mut() {
+                lease.keys.remove(&key);
+            }
+            let lease = self.current(&key).map_or(0, lease_of);
+            if let Some(attached) = self.leases.get_mut(&lease) {
+                attached.keys.insert(key);
+            }
+        }
+        self.dirty = false;
+    }
+
+    fn record(&mut self, key: Vec<u8>, rev: i64, kv: Option<KeyValue>) {
+        self.dirty = true;
+        self.keys.entry(key).or_default().push(Record { rev, kv });
+    }
+
+    /// Events for keys in `span` between revisions `from` and `to` inclusive, grouped by revision.
+    fn events(&self, span: &Span, from: i64, to: i64) -> BTreeMap<i64, Vec<Event>> {
+        let mut events: BTreeMap<i64, Vec<Event>> = BTreeMap::new();
+        for (key, records) in self.keys.iter().filter(|(key, _)| span.contains(key)) {
+            for (i, record) in records.iter().enumerate() {
+                if record.rev < from || record.rev > to {
+                    continue;
+                }
+                let prev = i.checked_sub(1).and_then(|i| records[i].kv.clone());
+                let event = match record.kv {
+                    Some(ref kv) => Event::new(EventType::PUT, kv.clone(), prev),
+                    None => {
+                        let mut kv = KeyValue::default();
+                        kv.set_key(key);
+                        kv.mod_revision = Some(record.rev.to_string());
+                        Event::new(EventType::DELETE, kv, prev)
+                    }
+                };
+                events.entry(record.rev).or_default().push(event);
+            }
+        }
+        events
+    }
+
+    fn notify(&mut self, rev: i64) {
+        let watchers = std::mem::take(&mut self.watchers);
+        self.watchers = watchers
+            .into_iter()
+            .filter(|watcher| {
+                let events = self.events(&watcher.span, rev, rev).remove(&rev);
+                watcher.deliver(rev, events.unwrap_or_default())
+            })
+            .collect();
+    }
+
+    fn watch(&mut self, req: WatchCreateRequest) -> BoxStream<'static, Result<WatchResponse>> {
+        let (sender, receiver) = mpsc::unbounded();
+        self.last_watch += 1;
+        let filters = req.filters.unwrap_or_default();
+        let watcher = Watcher {
+            id: self.last_watch,
+            span: Span::new(&req.key, &req.range_end),
+            no_put: filters.iter().any(|f| matches!(f, FilterType::NOPUT)),
+            no_delete: filters.iter().any(|f| matches!(f, FilterType::NODELETE)),
+            prev_kv: req.prev_kv == Some(true),
+            sender,
+        };
+        let created = WatchResponse {
+            created: Some(true),
+            ..watch_response(self.rev, watcher.id, None)
+        };
+        let _ = watcher.sender.unbounded_send(Ok(created));
+        let start = number(&req.start_revision);
+        if start > 0 && start <= self.rev {
+            for (rev, events) in self.events(&watcher.span, start, self.rev) {
+                watcher.deliver(rev, events);
+            }
+        }
+        self.watchers.push(watcher);
+        receiver.boxed()
+    }
+}
+
+impl Watcher {
+    /// Send the events of revision `rev` this watcher is interested in, returning whether it is
+    /// still being listened to.
+    fn deliver(&self, rev: i64, events: Vec<Event>) -> bool {
+        let events: Vec<Event> = events
+            .into_iter()
+            .filter(|event| match event.event_type() {
+                Some(EventType::DELETE) => !self.no_delete,
+                _ => !self.no_put,
+            })
+            .map(|mut event| {
+                if !self.prev_kv {
+                    event.prev_kv = None;
+                }
+                event
+            })
+            .collect();
+        if events.is_empty() {
+            return !self.sender.is_closed();
+        }
+        self.sender
+            .unbounded_send(Ok(watch_response(rev, self.id, Some(events))))
+            .is_ok()
+    }
+}
+
+/// Header for a response at revision `rev`, from a single member cluster with fixed ids.
+pub(crate) fn header(rev: i64) -> ResponseHeader {
+    ResponseHeader {
+        cluster_id: Some(String::from("1")),
+        member_id: Some(String::from("1")),
+        raft_term: Some(String::from("1")),
+        ..ResponseHeader::new(rev)
+    }
+}
+
+fn watch_response(rev: i64, id: i64, events: Option<Vec<Event>>) -> WatchResponse {
+    WatchResponse {
+        header: Some(header(rev)),
+        watch_id: Some(id.to_string()),
+        created: None,
+        canceled: None,
+        compact_revision: None,
+        cancel_reason: None,
+        events,
+    }
+}
+
+fn decode(field: &Option<String>) -> Vec<u8> {
+    field
+        .as_ref()
+        .and_then(|v| base64::decode(v).ok())
+        .unwrap_or_default()
+}
+
+fn number(field: &Option<String>) -> i64 {
+    field.as_ref().map_or(0, |v| v.parse::<i64>().unwrap_or(0))
+}
+
+fn version_of(kv: &KeyValue) -> i64 {
+    number(&kv.version)
+}
+
+fn lease_of(kv: &KeyValue) -> i64 {
+    number(&kv.lease)
+}
+
+fn without_value(kv: &KeyValue) -> KeyValue {
+    let mut stripped = KeyValue::default();
+    stripped.set_key(&kv.key_as_u8().unwrap_or_default());
+    stripped.create_revision = kv.create_revision.clone();
+    stripped.mod_revision = kv.mod_revision.clone();
+    stripped.version = kv.version.clone();
+    stripped.lease = kv.lease.clone();
+    stripped
+}
+
+fn sort_key(target: SortTarget, a: &KeyValue, b: &KeyValue) -> Ordering {
+    match target {
+        SortTarget::KEY => a.key_as_u8().cmp(&b.key_as_u8()),
+        SortTarget::CREATE => a.create_rev().cmp(&b.create_rev()),
+        SortTarget::VERSION => version_of(a).cmp(&version_of(b)),
+        SortTarget::MOD => a.mod_rev().cmp(&b.mod_rev()),
+        SortTarget::VALUE => a.value_as_u8().cmp(&b.value_as_u8()),
+    }
+}
+
+fn compare_kv(compare: &Compare, kv: &KeyValue) -> bool {
+    let ord = match compare.target_union {
+        CompareTargetUnion::Version(ref v) => version_of(kv).cmp(&number(&Some(v.clone()))),
+        CompareTargetUnion::CreateRevision(ref v) => kv.create_rev().cmp(&number(&Some(v.clone()))),
+        CompareTargetUnion::ModRevision(ref v) => kv.mod_rev().cmp(&number(&Some(v.clone()))),
+        CompareTargetUnion::Lease(ref v) => lease_of(kv).cmp(&number(&Some(v.clone()))),
+        CompareTargetUnion::Value(ref v) => kv
+            .value_as_u8()
+            .unwrap_or_default()
+            .cmp(&decode(&Some(v.clone()))),
+    };
+    match compare.result {
+        Some(CompareResult::EQUAL) | None => ord == Ordering::Equal,
+        Some(CompareResult::GREATER) => ord == Ordering::Greater,
+        Some(CompareResult::LESS) => ord == Ordering::Less,
+        Some(CompareResult::NOT_EQUAL) => ord != Ordering::Equal,
+    }
+}
+
+impl Kv for MemoryStore {
+    fn put<'a>(&'a self, key: &'a str, val: &'a str) -> BoxFuture<'a, Result<bool>> {
+        let put = self.write(|state, rev| state.put(rev, &PutRequest::new(key, val)));
+        future::ready(put.map(|_| true)).boxed()
+    }
+
+    fn put_with_lease<'a>(
+        &'a self,
+        key: &'a str,
+        val: &'a str,
+        lease: i64,
+    ) -> BoxFuture<'a, Result<bool>> {
+        let req = PutRequest::new_with_lease(key, val, lease);
+        let put = self.write(|state, rev| state.put(rev, &req));
+        future::ready(put.map(|_| true)).boxed()
+    }
+
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<String>>> {
+        let resp = self.range_raw(&RangeRequest::new(key));
+        let value = resp.map(|resp| {
+            resp.kvs
+                .and_then(|kvs| kvs.into_iter().next())
+                .and_then(|kv| kv.value())
+        });
+        future::ready(value).boxed()
+    }
+
+    fn get_prefix<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Vec<(String, String)>>> {
+        let resp = self.range_raw(&RangeRequest::new_for_prefix(key));
+        let values = resp.map(|resp| {
+            resp.kvs
+                .unwrap_or_default()
+                .iter()
+                .map(|kv| (kv.key().unwrap(), kv.value().unwrap_or_default()))
+                .collect()
+        });
+        future::ready(values).boxed()
+    }
+
+    fn range<'a>(&'a self, opts: &'a RangeOptions) -> BoxFuture<'a, Result<RangeResult>> {
+        future::ready(self.range_raw(opts.request()).map(RangeResult::from)).boxed()
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<usize>> {
+        let req = DeleteRangeRequest::new(key);
+        let deleted = self.write(|state, rev| Ok(state.delete_range(rev, &req)));
+        future::ready(deleted.map(|(resp, _)| resp.deleted())).boxed()
+    }
+
+    fn delete_prefix<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<usize>> {
+        let req = DeleteRangeRequest::new_for_prefix(prefix);
+        let deleted = self.write(|state, rev| Ok(state.delete_range(rev, &req)));
+        future::ready(deleted.map(|(resp, _)| resp.deleted())).boxed()
+    }
+
+    fn txn<'a>(&'a self, req: &'a TxnRequest) -> BoxFuture<'a, Result<TxnResponse>> {
+        let resp = self
+            .write(|state, rev| state.txn(rev, req))
+            .map(|(mut resp, rev)| {
+                stamp(&mut resp, rev);
+                resp
+            });
+        future::ready(resp).boxed()
+    }
+}
+
+/// Set the header of a transaction response and every nested response to revision `rev`.
+fn stamp(resp: &mut TxnResponse, rev: i64) {
+    resp.header = Some(header(rev));
+    for op in resp.responses.iter_mut().flatten() {
+        match *op {
+            ResponseOp::Range(ref mut resp) => resp.header = Some(header(rev)),
+            ResponseOp::Put(ref mut resp) => resp.header = Some(header(rev)),
+            ResponseOp::DeleteRange(ref mut resp) => resp.header = Some(header(rev)),
+            ResponseOp::Txn(ref mut resp) => stamp(resp, rev),
+        }
+    }
+}
+
+impl Watch for MemoryStore {
+    fn watch_with(
+        &self,
+        create_request: WatchCreateRequest,
+    ) -> BoxFuture<'_, Result<BoxStream<'static, Result<WatchResponse>>>> {
+        future::ready(Ok(self.lock().watch(create_request))).boxed()
+    }
+}
+
+impl Lease for MemoryStore {
+    fn lease_grant(&self, ttl: i64) -> BoxFuture<'_, Result<LeaseGrantResponse>> {
+        let mut state = self.lock();
+        let mut resp = state.grant(ttl);
+        resp.header = Some(header(state.rev));
+        future::ready(Ok(resp)).boxed()
+    }
+
+    fn lease_revoke(&self, id: i64) -> BoxFuture<'_, Result<()>> {
+        future::ready(self.lock().revoke(id)).boxed()
+    }
+
+    fn lease_keep_alive(&self, id: i64) -> BoxFuture<'_, Result<LeaseKeepAliveResponse>> {
+        let mut state = self.lock();
+        let mut resp = state.keep_alive(id);
+        resp.header = Some(header(state.rev));
+        future::ready(Ok(resp)).boxed()
+    }
+}
@@ -0,0 +1,290 @@
+//! Replicating a prefix from one cluster to another, e.g., to keep a disaster recovery cluster in
+//! sync. A `Mirror` copies the prefix as of a single revision, then applies the changes the
+//! source reports after it, one destination transaction per source revision. Once the initial
+//! copy completes, the destination holds the prefix as it was at some revision of the source;
+//! while it is still running, the destination may hold only part of that revision.
+//!
+//! Keys on the destination that do not exist on the source are left alone, and leases are not
+//! mirrored since lease IDs do not carry over between clusters.
+
+use super::etcd_actions::EtcdSession;
+use super::etcd_error::{Error, Result};
+use super::etcd_proto::*;
+use futures::TryStreamExt;
+use std::cmp;
+use std::io;
+use std::mem;
+use std::sync::atomic::{AtomicI64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Keys written per transaction while copying, which stays below the default `--max-txn-ops`.
+const COPY_BATCH: usize = 64;
+
+/// Progress of a `Mirror`. Clones share the same counters, so a handle can be read while the
+/// mirror runs.
+#[derive(Clone, Default)]
+pub struct MirrorMetrics {
+    inner: Arc<Counters>,
+}
+
+#[derive(Default)]
+struct Counters {
+    source_revision: AtomicI64,
+    applied_revision: AtomicI64,
+    keys_copied: AtomicUsize,
+    events_applied: AtomicUsize,
+}
+
+impl MirrorMetrics {
+    /// Latest revision of the source the mirror has heard of.
+    pub fn source_revision(&self) -> i64 {
+        self.inner.source_revision.load(Ordering::SeqCst)
+    }
+
+    /// Source revision the destination is known to be in sync with.
+    pub fn applied_revision(&self) -> i64 {
+        self.inner.applied_revision.load(Ordering::SeqCst)
+    }
+
+    /// Number of source revisions not yet applied to the destination. Revisions that do not
+    /// touch the prefix only count as applied once the source reports progress, so this may
+    /// overestimate the lag while the prefix is idle.
+    pub fn lag(&self) -> i64 {
+        cmp::max(self.source_revision() - self.applied_revision(), 0)
+    }
+
+    /// Keys written by the initial copy.
+    pub fn keys_copied(&self) -> usize {
+        self.inner.keys_copied.load(Ordering::SeqCst)
+    }
+
+    /// Puts and deletes applied since the initial copy.
+    pub fn events_applied(&self) -> usize {
+        self.inner.events_applied.load(Ordering::SeqCst)
+    }
+
+    fn observe(&self, source_revision: i64) {
+        self.inner
+            .source_revision
+            .fetch_max(source_revision, Ordering::SeqCst);
+    }
+
+    fn applied(&self, revision: i64) {
+        self.inner
+            .applied_revision
+            .fetch_max(revision, Ordering::SeqCst);
+        self.observe(revision);
+    }
+}
+
+/// Replicates every key starting with a prefix from `source` to `destination`.
+pub struct Mirror {
+    source: EtcdSession,
+    destination: EtcdSession,
+    prefix: String,
+    destination_prefix: String,
+    checkpoint_key: Option<String>,
+    resume_from: Option<i64>,
+    page_size: usize,
+    metrics: MirrorMetrics,
+}
+
+impl Mirror {
+    pub fn new(source: &EtcdSession, destination: &EtcdSession, prefix: &str) -> Mirror {
+        Mirror {
+            source: source.clone(),
+            destination: destination.clone(),
+            prefix: String::from(prefix),
+            destination_prefix: String::from(prefix),
+            checkpoint_key: None,
+            resume_from: None,
+            page_size: 1000,
+            metrics: MirrorMetrics::default(),
+        }
+    }
+
+    /// Write keys under `prefix` on the destination, in place of the source prefix.
+    pub fn rewrite_prefix(mut self, prefix: &str) -> Mirror {
+        self.destination_prefix = String::from(prefix);
+        self
+    }
+
+    /// Record the last applied source revision in `key` on the destination, in the same
+    /// transaction as the changes. If the key exists when the mirror starts, it resumes from that
+    /// revision instead of copying the prefix again. The key must not be under the mirrored
+    /// prefix of the source.
+    pub fn checkpoint_key(mut self, key: &str) -> Mirror {
+        self.checkpoint_key = Some(String::from(key));
+        self
+    }
+
+    /// Skip the initial copy and apply the changes made after `revision`, e.g., one saved from
+    /// `MirrorMetrics::applied_revision`. This takes precedence over the checkpoint key.
+    pub fn resume_from(mut self, revision: i64) -> Mirror {
+        self.resume_from = Some(revision);
+        self
+    }
+
+    /// Number of keys fetched per request during the initial copy.
+    pub fn page_size(mut self, page_size: usize) -> Mirror {
+        self.page_size = page_size;
+        self
+    }
+
+    pub fn metrics(&self) -> MirrorMetrics {
+        self.metrics.clone()
+    }
+
+    /// Copy the prefix unless resuming, then apply changes until the source watch ends, e.g.,
+    /// through `EtcdSession::cancel_watches`. Fails if the source has compacted revisions that
+    /// have not been applied yet, in which case the mirror has to start over with a full copy.
+    pub async fn run(&self) -> Result<()> {
+        let rev = match self.resume_point().await? {
+            Some(rev) => rev,
+            None => self.copy().await?,
+        };
+        self.metrics.applied(rev);
+        let create_request = WatchCreateRequest {
+            start_revision: Some((rev + 1).to_string()),
+            progress_notify: Some(true),
+            ..WatchCreateRequest::new_for_prefix(&self.prefix)
+        };
+        let mut watch = self.source.watch_with(create_request).await?;
+        while let Some(resp) = watch.try_next().await? {
+            if resp.canceled == Some(true) {
+                let reason = match resp.compact_revision {
+                    Some(ref compacted) => format!("source compacted up to revision {}", compacted),
+                    None => resp.cancel_reason.unwrap_or_default(),
+                };
+                return Err(Error::Io(io::Error::other(format!(
+                    "mirror watch cancelled: {}",
+                    reason
+                ))));
+            }
+            let header = resp.header.as_ref().map_or(0, ResponseHeader::rev);
+            self.metrics.observe(header);
+            let events = resp.events.unwrap_or_default();
+            if events.is_empty() {
+                // A progress notification means every change up to its revision was delivered.
+                if resp.created != Some(true) {
+                    self.metrics.applied(header);
+                }
+                continue;
+            }
+            let mut ops = Vec::new();
+            let mut current = None;
+            for event in &events {
+                let rev = event.kv.as_ref().map_or(0, KeyValue::mod_rev);
+                match current {
+                    Some(current) if current != rev => {
+                        self.apply(current, mem::take(&mut ops)).await?
+                    }
+                    _ => {}
+                }
+                current = Some(rev);
+                ops.push(self.op(event));
+            }
+            if let Some(rev) = current {
+                self.apply(rev, ops).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn resume_point(&self) -> Result<Option<i64>> {
+        if self.resume_from.is_some() {
+            return Ok(self.resume_from);
+        }
+        match self.checkpoint_key {
+            Some(ref key) => Ok(self
+                .destination
+                .get(key)
+                .await?
+                .and_then(|rev| rev.parse().ok())),
+            None => Ok(None),
+        }
+    }
+
+    /// Copy the prefix as of the current revision of the source, resolving to that revision.
+    async fn copy(&self) -> Result<i64> {
+        let req = RangeRequest {
+            count_only: Some(true),
+            ..RangeRequest::new_for_prefix(&self.prefix)
+        };
+        let resp = self.source.range_raw(&req).await?;
+        let rev = resp.header.as_ref().map_or(0, ResponseHeader::rev);
+        let mut kvs = self
+            .source
+            .scan_prefix_at(&self.prefix, self.page_size, rev);
+        let mut ops = Vec::with_capacity(COPY_BATCH);
+        while let Some(kv) = kvs.try_next().await? {
+            ops.push(RequestOp::Put(self.put(&kv)));
+            if ops.len() == COPY_BATCH {
+                self.copy_batch(mem::take(&mut ops)).await?;
+            }
+        }
+        if !ops.is_empty() {
+            self.copy_batch(ops).await?;
+        }
+        self.apply(rev, Vec::new()).await?;
+        Ok(rev)
+    }
+
+    async fn copy_batch(&self, ops: Vec<RequestOp>) -> Result<()> {
+        let keys = ops.len();
+        self.destination
+            .txn(&TxnRequest::new(vec![], ops, vec![]))
+            .await?;
+        self.metrics
+            .inner
+            .keys_copied
+            .fetch_add(keys, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Apply the changes of source revision `rev`, along with the checkpoint.
+    async fn apply(&self, rev: i64, mut ops: Vec<RequestOp>) -> Result<()> {
+        let events = ops.len();
+        if let Some(ref key) = self.checkpoint_key {
+            ops.push(RequestOp::Put(PutRequest::new(key, &rev.to_string())));
+        }
+        if !ops.is_empty() {
+            self.destination
+                .txn(&TxnRequest::new(vec![], ops, vec![]))
+                .await?;
+        }
+        self.metrics
+            .inner
+            .events_applied
+            .fetch_add(events, Ordering::SeqCst);
+        self.metrics.applied(rev);
+        Ok(())
+    }
+
+    fn op(&self, event: &Event) -> RequestOp {
+        let kv = event.kv.clone().unwrap_or_default();
+        match event.event_type() {
+            Some(EventType::DELETE) => RequestOp::DeleteRange(DeleteRangeRequest {
+                key: Some(base64::encode(&self.rewrite(&kv))),
+                ..DeleteRangeRequest::new("")
+            }),
+            _ => RequestOp::Put(self.put(&kv)),
+        }
+    }
+
+    fn put(&self, kv: &KeyValue) -> PutRequest {
+        PutRequest {
+            key: Some(base64::encode(&self.rewrite(kv))),
+            ..PutRequest::new_bytes("", &kv.value_as_u8().unwrap_or_default())
+        }
+    }
+
+    /// The destination key for `kv`.
+    fn rewrite(&self, kv: &KeyValue) -> Vec<u8> {
+        let key = kv.key_as_u8().unwrap_or_default();
+        match key.strip_prefix(self.prefix.as_bytes()) {
+            Some(rest) => [self.destination_prefix.as_bytes(), rest].concat(),
+            None => key,
+        }
+    }
+}
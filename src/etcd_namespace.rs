@@ -0,0 +1,217 @@
+use super::etcd_actions::EtcdSession;
+use super::etcd_error::Result;
+use super::etcd_proto::*;
+use futures::stream::BoxStream;
+use futures::{StreamExt, TryStreamExt};
+
+/// A view of an `EtcdSession` confined to keys under a prefix, like Go's `namespace` package. Keys
+/// and range ends are prefixed on the way out and the prefix is stripped from every returned
+/// `KeyValue`, so callers never see it.
+#[derive(Clone)]
+pub struct Namespace {
+    session: EtcdSession,
+    prefix: String,
+}
+
+impl Namespace {
+    pub fn new(session: &EtcdSession, prefix: &str) -> Namespace {
+        Namespace {
+            session: session.clone(),
+            prefix: String::from(prefix),
+        }
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub async fn put(&self, key: &str, val: &str) -> Result<bool> {
+        self.session
+            .put(&format!("{}{}", self.prefix, key), val)
+            .await
+    }
+
+    pub async fn get(&self, key: &str) -> Result<Option<String>> {
+        self.session.get(&format!("{}{}", self.prefix, key)).await
+    }
+
+    pub async fn get_prefix(&self, key: &str) -> Result<Vec<(String, String)>> {
+        let len = self.prefix.len();
+        let kvs = self
+            .session
+            .get_prefix(&format!("{}{}", self.prefix, key))
+            .await?;
+        Ok(kvs
+            .into_iter()
+            .map(|(k, v)| (String::from(&k[len..]), v))
+            .collect())
+    }
+
+    pub async fn get_prefix_raw(&self, key: &str) -> Result<RangeResponse> {
+        let mut resp = self
+            .session
+            .get_prefix_raw(&format!("{}{}", self.prefix, key))
+            .await?;
+        self.strip_range(&mut resp);
+        Ok(resp)
+    }
+
+    pub async fn delete(&self, key: &str) -> Result<usize> {
+        self.session
+            .delete(&format!("{}{}", self.prefix, key))
+            .await
+    }
+
+    pub async fn delete_prefix(&self, key: &str) -> Result<usize> {
+        self.session
+            .delete_prefix(&format!("{}{}", self.prefix, key))
+            .await
+    }
+
+    /// Execute a transaction, every key it compares or operates on is taken relative to the
+    /// prefix.
+    pub async fn txn(&self, req: &TxnRequest) -> Result<TxnResponse> {
+        let mut req = req.clone();
+        self.prefix_txn(&mut req);
+        let mut resp = self.session.txn(&req).await?;
+        self.strip_txn(&mut resp);
+        Ok(resp)
+    }
+
+    /// Create a new stream that reports changes to a key.
+    pub async fn watch(&self, key: &str) -> Result<BoxStream<'static, Result<WatchResponse>>> {
+        self.watch_with(WatchCreateRequest::new_for_key(key)).await
+    }
+
+    /// Create a new stream that reports changes to keys starting with `key`.
+    pub async fn watch_pfx(&self, key: &str) -> Result<BoxStream<'static, Result<WatchResponse>>> {
+        self.watch_with(WatchCreateRequest::new_for_prefix(key))
+            .await
+    }
+
+    /// Create a new stream for an arbitrary watch, relative to the prefix.
+    pub async fn watch_with(
+        &self,
+        mut create_request: WatchCreateRequest,
+    ) -> Result<BoxStream<'static, Result<WatchResponse>>> {
+        (create_request.key, create_request.range_end) =
+            self.prefixed_range(&create_request.key, &create_request.range_end);
+        let ns = self.clone();
+        let stream = self.session.watch_with(create_request).await?;
+        Ok(stream
+            .map_ok(move |mut resp| {
+                if let Some(ref mut events) = resp.events {
+                    for event in events.iter_mut() {
+                        ns.strip_opt(&mut event.kv);
+                        ns.strip_opt(&mut event.prev_kv);
+                    }
+                }
+                resp
+            })
+            .boxed())
+    }
+
+    fn prefixed(&self, key: &Option<String>) -> Option<String> {
+        key.as_ref().map(|k| {
+            let mut prefixed = self.prefix.clone().into_bytes();
+            prefixed.extend(base64::decode(k).unwrap());
+            base64::encode(&prefixed[..])
+        })
+    }
+
+    /// Prefix a `key` and `range_end` pair. A range starting at "\0", which is how every key is
+    /// selected, starts at the prefix itself instead.
+    fn prefixed_range(
+        &self,
+        key: &Option<String>,
+        range_end: &Option<String>,
+    ) -> (Option<String>, Option<String>) {
+        let everything = Some(base64::encode("\0"));
+        match range_end {
+            Some(_) if *key == everything && !self.prefix.is_empty() => (
+                Some(base64::encode(&self.prefix)),
+                self.prefixed_end(range_end),
+            ),
+            _ => (self.prefixed(key), self.prefixed_end(range_end)),
+        }
+    }
+
+    fn prefixed_end(&self, range_end: &Option<String>) -> Option<String> {
+        range_end.as_ref().map(|end| {
+            let end = base64::decode(end).unwrap();
+            if end == [0] {
+                // "Every key from here on" must stop at the end of the namespace.
+                base64::encode(&prefix_range_end(self.prefix.as_bytes())[..])
+            } else {
+                let mut prefixed = self.prefix.clone().into_bytes();
+                prefixed.extend(end);
+                base64::encode(&prefixed[..])
+            }
+        })
+    }
+
+    fn prefix_txn(&self, txn: &mut TxnRequest) {
+        if let Some(ref mut compare) = txn.compare {
+            for cmp in compare.iter_mut() {
+                (cmp.key, cmp.range_end) = self.prefixed_range(&cmp.key, &cmp.range_end);
+            }
+        }
+        for ops in txn.success.iter_mut().chain(txn.failure.iter_mut()) {
+            for op in ops.iter_mut() {
+                match *op {
+                    RequestOp::Range(ref mut range) => {
+                        (range.key, range.range_end) =
+                            self.prefixed_range(&range.key, &range.range_end);
+                    }
+                    RequestOp::Put(ref mut put) => {
+                        put.key = self.prefixed(&put.key);
+                    }
+                    RequestOp::DeleteRange(ref mut delete) => {
+                        (delete.key, delete.range_end) =
+                            self.prefixed_range(&delete.key, &delete.range_end);
+                    }
+                    RequestOp::Txn(ref mut txn) => self.prefix_txn(txn),
+                }
+            }
+        }
+    }
+
+    fn strip(&self, kv: &mut KeyValue) {
+        if let Some(key) = kv.key_as_u8() {
+            if key.starts_with(self.prefix.as_bytes()) {
+                kv.set_key(&key[self.prefix.len()..]);
+            }
+        }
+    }
+
+    fn strip_opt(&self, kv: &mut Option<KeyValue>) {
+        if let Some(ref mut kv) = *kv {
+            self.strip(kv);
+        }
+    }
+
+    fn strip_all(&self, kvs: &mut Option<Vec<KeyValue>>) {
+        if let Some(ref mut kvs) = *kvs {
+            for kv in kvs.iter_mut() {
+                self.strip(kv);
+            }
+        }
+    }
+
+    fn strip_range(&self, resp: &mut RangeResponse) {
+        self.strip_all(&mut resp.kvs);
+    }
+
+    fn strip_txn(&self, resp: &mut TxnResponse) {
+        if let Some(ref mut responses) = resp.responses {
+            for op in responses.iter_mut() {
+                match *op {
+                    ResponseOp::Range(ref mut range) => self.strip_range(range),
+                    ResponseOp::Put(ref mut put) => self.strip_opt(&mut put.prev_kv),
+                    ResponseOp::DeleteRange(ref mut delete) => self.strip_all(&mut delete.prev_kvs),
+                    ResponseOp::Txn(ref mut txn) => self.strip_txn(txn),
+                }
+            }
+        }
+    }
+}
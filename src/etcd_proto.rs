@@ -1,16 +1,15 @@
-use base64;
-/// Hand crafted structures from the `etcd` protobuf. This allows us to call into etcd from Rust.
-/// Some weird things to note when adding to this file:
-///   - All fields in proto3 are optional -- as a result every field needs to be marked as an
-///   `Option<T>`. This is necessary to guarantee that changes in the `etcd` implementation
-///   do not cause problems.
-///   - The `gRPC` bridge converse 64-bit numbers into Strings.
-///   - `oneof` needs to be implemented using enums -- `gRPC` bridge barfs if a null element of the
-///   other type is included.
-///   - Stream responses are encoded in a struct with result as a field.
+//! Hand crafted structures from the `etcd` protobuf. This allows us to call into etcd from Rust.
+//! Some weird things to note when adding to this file:
+//!   - All fields in proto3 are optional -- as a result every field needs to be marked as an
+//!     `Option<T>`. This is necessary to guarantee that changes in the `etcd` implementation
+//!     do not cause problems.
+//!   - The `gRPC` bridge converse 64-bit numbers into Strings.
+//!   - `oneof` needs to be implemented using enums -- `gRPC` bridge barfs if a null element of the
+//!     other type is included.
+//!   - Stream responses are encoded in a struct with result as a field.
 
 /// Common response header included in every `etcd` response.
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct ResponseHeader {
     pub cluster_id: Option<String>,
     pub member_id: Option<String>,
@@ -18,8 +17,27 @@ pub struct ResponseHeader {
     pub raft_term: Option<String>,
 }
 
+impl ResponseHeader {
+    pub fn new(revision: i64) -> ResponseHeader {
+        ResponseHeader {
+            cluster_id: None,
+            member_id: None,
+            revision: Some(revision.to_string()),
+            raft_term: None,
+        }
+    }
+
+    /// Store revision at the time the request was applied.
+    pub fn rev(&self) -> i64 {
+        self.revision
+            .as_ref()
+            .map(|v| v.parse::<i64>().unwrap())
+            .unwrap_or(0)
+    }
+}
+
 /// Mechanism to encode `etcd` key-value responses.
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize, Clone, Default)]
 pub struct KeyValue {
     key: Option<String>,
     pub create_revision: Option<String>,
@@ -30,22 +48,40 @@ pub struct KeyValue {
 }
 
 impl KeyValue {
+    pub fn key_as_u8(&self) -> Option<Vec<u8>> {
+        match self.key {
+            Some(ref k) => base64::decode(&k).ok(),
+            None => None,
+        }
+    }
+
+    pub fn set_key(&mut self, key: &[u8]) {
+        self.key = Some(base64::encode(key));
+    }
+
+    pub fn set_value(&mut self, value: &[u8]) {
+        self.value = Some(base64::encode(value));
+    }
+
+    /// The key as text, with invalid UTF-8 replaced by U+FFFD. Use `key_as_u8` for the raw bytes.
     pub fn key(&self) -> Option<String> {
         match self.key {
             Some(ref k) => {
                 base64::decode(&k).ok().map(
-                    |v| String::from_utf8(v).unwrap(),
+                    |v| String::from_utf8_lossy(&v).into_owned(),
                 )
             }
             None => None,
         }
     }
 
+    /// The value as text, with invalid UTF-8 replaced by U+FFFD, e.g., for values written by a
+    /// binary codec. Use `value_as_u8` for the raw bytes.
     pub fn value(&self) -> Option<String> {
         match self.value {
             Some(ref v) => {
                 base64::decode(&v).ok().map(
-                    |v| String::from_utf8(v).unwrap(),
+                    |v| String::from_utf8_lossy(&v).into_owned(),
                 )
             }
             None => None,
@@ -58,17 +94,32 @@ impl KeyValue {
             None => None,
         }
     }
+
+    /// Revision at which the key was created, this orders waiters in lock-style recipes.
+    pub fn create_rev(&self) -> i64 {
+        self.create_revision
+            .as_ref()
+            .map(|v| v.parse::<i64>().unwrap())
+            .unwrap_or(0)
+    }
+
+    pub fn mod_rev(&self) -> i64 {
+        self.mod_revision
+            .as_ref()
+            .map(|v| v.parse::<i64>().unwrap())
+            .unwrap_or(0)
+    }
 }
 
 /// Response for a `PutRequest`.
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct PutResponse {
     pub header: Option<ResponseHeader>,
     pub prev_kv: Option<KeyValue>, // Optional since is only set when prev_kv is true.
 }
 
 /// A `PutRequest` to add or overwrite a key for etcd.
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct PutRequest {
     pub key: Option<String>,
     pub value: Option<String>,
@@ -91,16 +142,32 @@ impl PutRequest {
         }
     }
 
+    /// Create a `PutRequest` for a value that need not be UTF-8.
+    pub fn new_bytes(key: &str, val: &[u8]) -> PutRequest {
+        PutRequest {
+            value: Some(base64::encode(val)),
+            ..PutRequest::new(key, "")
+        }
+    }
+
     pub fn new_with_previous(key: &str, val: &str) -> PutRequest {
         PutRequest {
             prev_kv: Some(true), // Do not get previous by default
             ..PutRequest::new(key, val)
         }
     }
+
+    /// Create a `PutRequest` for a key that is deleted when `lease` expires.
+    pub fn new_with_lease(key: &str, val: &str, lease: i64) -> PutRequest {
+        PutRequest {
+            lease: Some(lease.to_string()),
+            ..PutRequest::new(key, val)
+        }
+    }
 }
 
 // This is by default turned into a string by `serde_json`, hence encoding it correctly.
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
 pub enum SortOrder {
     NONE,
     ASCEND,
@@ -108,7 +175,7 @@ pub enum SortOrder {
 }
 
 // This is by default turned into a string by `serde_json`, hence encoding it correctly.
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
 pub enum SortTarget {
     KEY,
     CREATE,
@@ -117,7 +184,34 @@ pub enum SortTarget {
     VALUE,
 }
 
-#[derive(Serialize, Default)]
+/// The end of the range holding every key that starts with `prefix`: the prefix with trailing
+/// 0xff bytes dropped and its last byte incremented. An empty prefix, or one made only of 0xff
+/// bytes, has no such end and maps to "\0", which `etcd` reads as "every key from here on".
+pub fn prefix_range_end(prefix: &[u8]) -> Vec<u8> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < 0xff {
+            end.push(last + 1);
+            return end;
+        }
+    }
+    vec![0]
+}
+
+/// The base64 encoded `key` and `range_end` selecting every key that starts with `prefix`. The
+/// empty prefix starts from "\0", since `etcd` rejects an empty key.
+fn prefix_range(prefix: &str) -> (Option<String>, Option<String>) {
+    let key = match prefix.is_empty() {
+        true => vec![0],
+        false => prefix.as_bytes().to_vec(),
+    };
+    (
+        Some(base64::encode(&key[..])),
+        Some(base64::encode(&prefix_range_end(prefix.as_bytes())[..])),
+    )
+}
+
+#[derive(Serialize, Deserialize, Default, Clone)]
 pub struct RangeRequest {
     pub key: Option<String>,
     pub range_end: Option<String>,
@@ -151,12 +245,10 @@ impl RangeRequest {
     }
 
     pub fn new_for_prefix(key: &str) -> RangeRequest {
-        let mut range_end = String::from(key).into_bytes();
-        let end = key.len() - 1;
-        range_end[end] += 1;
+        let (key, range_end) = prefix_range(key);
         RangeRequest {
-            key: Some(base64::encode(key)),
-            range_end: Some(base64::encode(&range_end[..])),
+            key,
+            range_end,
             ..Default::default()
         }
     }
@@ -174,7 +266,7 @@ impl RangeRequest {
     }
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct RangeResponse {
     pub header: Option<ResponseHeader>,
     pub kvs: Option<Vec<KeyValue>>,
@@ -183,6 +275,15 @@ pub struct RangeResponse {
 }
 
 impl RangeResponse {
+    pub fn new(kvs: Vec<KeyValue>, more: bool, count: usize) -> RangeResponse {
+        RangeResponse {
+            header: None,
+            kvs: Some(kvs),
+            more: Some(more),
+            count: Some(count.to_string()),
+        }
+    }
+
     pub fn count(&self) -> usize {
         self.count
             .as_ref()
@@ -191,9 +292,238 @@ impl RangeResponse {
     }
 }
 
+/// Request to delete a key, or all keys in `[key, range_end)`.
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct DeleteRangeRequest {
+    pub key: Option<String>,
+    pub range_end: Option<String>,
+    pub prev_kv: Option<bool>,
+}
+
+impl DeleteRangeRequest {
+    pub fn new(key: &str) -> DeleteRangeRequest {
+        DeleteRangeRequest {
+            key: Some(base64::encode(key)),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_for_prefix(key: &str) -> DeleteRangeRequest {
+        let (key, range_end) = prefix_range(key);
+        DeleteRangeRequest {
+            key,
+            range_end,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DeleteRangeResponse {
+    pub header: Option<ResponseHeader>,
+    deleted: Option<String>,
+    pub prev_kvs: Option<Vec<KeyValue>>,
+}
+
+impl DeleteRangeResponse {
+    pub fn new(deleted: usize, prev_kvs: Option<Vec<KeyValue>>) -> DeleteRangeResponse {
+        DeleteRangeResponse {
+            header: None,
+            deleted: Some(deleted.to_string()),
+            prev_kvs,
+        }
+    }
+
+    pub fn deleted(&self) -> usize {
+        self.deleted
+            .as_ref()
+            .map(|v| v.parse::<usize>().unwrap())
+            .unwrap_or(0)
+    }
+}
+
+// This is by default turned into a string by `serde_json`, hence encoding it correctly.
+#[derive(Serialize, Deserialize, Clone)]
+#[allow(non_camel_case_types)]
+pub enum CompareResult {
+    EQUAL,
+    GREATER,
+    LESS,
+    NOT_EQUAL,
+}
+
+// This is by default turned into a string by `serde_json`, hence encoding it correctly.
+#[derive(Serialize, Deserialize, Clone)]
+pub enum CompareTarget {
+    VERSION,
+    CREATE,
+    MOD,
+    VALUE,
+    LEASE,
+}
+
+/// The `oneof` holding the value a `Compare` checks against.
+#[derive(Serialize, Deserialize, Clone)]
+pub enum CompareTargetUnion {
+    #[serde(rename = "version")]
+    Version(String),
+    #[serde(rename = "create_revision")]
+    CreateRevision(String),
+    #[serde(rename = "mod_revision")]
+    ModRevision(String),
+    #[serde(rename = "value")]
+    Value(String),
+    #[serde(rename = "lease")]
+    Lease(String),
+}
+
+/// A guard for a `TxnRequest`, comparing one attribute of `key` against a value.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Compare {
+    pub result: Option<CompareResult>,
+    pub target: Option<CompareTarget>,
+    pub key: Option<String>,
+    pub range_end: Option<String>,
+    #[serde(flatten)]
+    pub target_union: CompareTargetUnion,
+}
+
+impl Compare {
+    fn new(
+        key: &str,
+        result: CompareResult,
+        target: CompareTarget,
+        target_union: CompareTargetUnion,
+    ) -> Compare {
+        Compare {
+            result: Some(result),
+            target: Some(target),
+            key: Some(base64::encode(key)),
+            range_end: None,
+            target_union,
+        }
+    }
+
+    pub fn new_version(key: &str, result: CompareResult, version: i64) -> Compare {
+        Compare::new(
+            key,
+            result,
+            CompareTarget::VERSION,
+            CompareTargetUnion::Version(version.to_string()),
+        )
+    }
+
+    /// A create revision of zero matches keys that do not exist.
+    pub fn new_create_revision(key: &str, result: CompareResult, revision: i64) -> Compare {
+        Compare::new(
+            key,
+            result,
+            CompareTarget::CREATE,
+            CompareTargetUnion::CreateRevision(revision.to_string()),
+        )
+    }
+
+    pub fn new_mod_revision(key: &str, result: CompareResult, revision: i64) -> Compare {
+        Compare::new(
+            key,
+            result,
+            CompareTarget::MOD,
+            CompareTargetUnion::ModRevision(revision.to_string()),
+        )
+    }
+
+    pub fn new_value(key: &str, result: CompareResult, value: &str) -> Compare {
+        Compare::new(
+            key,
+            result,
+            CompareTarget::VALUE,
+            CompareTargetUnion::Value(base64::encode(value)),
+        )
+    }
+
+    pub fn new_lease(key: &str, result: CompareResult, lease: i64) -> Compare {
+        Compare::new(
+            key,
+            result,
+            CompareTarget::LEASE,
+            CompareTargetUnion::Lease(lease.to_string()),
+        )
+    }
+}
+
+/// A `oneof` of the operations that can be executed inside a transaction.
+#[derive(Serialize, Deserialize, Clone)]
+pub enum RequestOp {
+    #[serde(rename = "request_range")]
+    Range(RangeRequest),
+    #[serde(rename = "request_put")]
+    Put(PutRequest),
+    #[serde(rename = "request_delete_range")]
+    DeleteRange(DeleteRangeRequest),
+    #[serde(rename = "request_txn")]
+    Txn(TxnRequest),
+}
+
+/// Atomically executes `success` if every comparison in `compare` holds, and `failure` otherwise.
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct TxnRequest {
+    pub compare: Option<Vec<Compare>>,
+    pub success: Option<Vec<RequestOp>>,
+    pub failure: Option<Vec<RequestOp>>,
+}
+
+impl TxnRequest {
+    pub fn new(
+        compare: Vec<Compare>,
+        success: Vec<RequestOp>,
+        failure: Vec<RequestOp>,
+    ) -> TxnRequest {
+        TxnRequest {
+            compare: Some(compare),
+            success: Some(success),
+            failure: Some(failure),
+        }
+    }
+}
+
+/// Result of a single operation in a transaction, in the same order as the request.
+#[derive(Serialize, Deserialize)]
+pub enum ResponseOp {
+    #[serde(rename = "response_range")]
+    Range(RangeResponse),
+    #[serde(rename = "response_put")]
+    Put(PutResponse),
+    #[serde(rename = "response_delete_range")]
+    DeleteRange(DeleteRangeResponse),
+    #[serde(rename = "response_txn")]
+    Txn(TxnResponse),
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TxnResponse {
+    pub header: Option<ResponseHeader>,
+    succeeded: Option<bool>,
+    pub responses: Option<Vec<ResponseOp>>,
+}
+
+impl TxnResponse {
+    pub fn new(succeeded: bool, responses: Vec<ResponseOp>) -> TxnResponse {
+        TxnResponse {
+            header: None,
+            succeeded: Some(succeeded),
+            responses: Some(responses),
+        }
+    }
+
+    /// Whether the comparisons held, i.e., whether the success branch was executed.
+    pub fn succeeded(&self) -> bool {
+        self.succeeded.unwrap_or(false)
+    }
+}
+
 // This looks different from everything else so we can retain `oneof` semantics.
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 pub enum WatchRequest {
     #[serde(rename = "create_request")]
     CreateRequest(WatchCreateRequest),
@@ -211,7 +541,7 @@ impl WatchRequest {
     }
 }
 
-#[derive(Serialize, Default)]
+#[derive(Serialize, Deserialize, Default)]
 pub struct WatchCancelRequest {
     pub watch_id: Option<String>,
 }
@@ -222,13 +552,13 @@ impl WatchCancelRequest {
     }
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 pub enum FilterType {
     NOPUT,
     NODELETE,
 }
 
-#[derive(Serialize, Default)]
+#[derive(Serialize, Deserialize, Default)]
 pub struct WatchCreateRequest {
     pub key: Option<String>,
     pub range_end: Option<String>,
@@ -246,12 +576,10 @@ impl WatchCreateRequest {
         }
     }
     pub fn new_for_prefix(key: &str) -> WatchCreateRequest {
-        let mut range_end = String::from(key).into_bytes();
-        let end = key.len() - 1;
-        range_end[end] += 1;
+        let (key, range_end) = prefix_range(key);
         WatchCreateRequest {
-            key: Some(base64::encode(key)),
-            range_end: Some(base64::encode(&range_end[..])),
+            key,
+            range_end,
             ..Default::default()
         }
     }
@@ -264,7 +592,7 @@ pub enum EventType {
 }
 
 /// An event from a set of watched keys.
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct Event {
     #[serde(rename = "type")]
     etype: Option<EventType>,
@@ -273,6 +601,14 @@ pub struct Event {
 }
 
 impl Event {
+    pub fn new(etype: EventType, kv: KeyValue, prev_kv: Option<KeyValue>) -> Event {
+        Event {
+            etype: Some(etype),
+            kv: Some(kv),
+            prev_kv,
+        }
+    }
+
     /// Type of event. Currently `etcd` seems to only set this when keys are deleted, puts
     /// (both for new keys and exisisting keys) do not show up with anything.
     pub fn event_type(&self) -> &Option<EventType> {
@@ -280,7 +616,7 @@ impl Event {
     }
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct WatchResponse {
     pub header: Option<ResponseHeader>,
     pub watch_id: Option<String>,
@@ -291,7 +627,436 @@ pub struct WatchResponse {
     pub events: Option<Vec<Event>>,
 }
 
-#[derive(Deserialize)]
+/// An error the gateway sends in place of a result on a streamed response, e.g., when `etcd`
+/// fails while the stream is open.
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct StreamError {
+    pub grpc_code: Option<i32>,
+    pub http_code: Option<i32>,
+    pub message: Option<String>,
+    pub http_status: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct WatchStreamResponse {
     pub result: Option<WatchResponse>,
+    /// Set instead of `result` when the gateway reports a failure in the middle of the stream.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<StreamError>,
+}
+
+/// Request to grant a lease with the given TTL (in seconds). An `ID` of zero lets `etcd` choose.
+#[derive(Serialize, Deserialize, Default)]
+pub struct LeaseGrantRequest {
+    #[serde(rename = "TTL")]
+    pub ttl: Option<String>,
+    #[serde(rename = "ID")]
+    pub id: Option<String>,
+}
+
+impl LeaseGrantRequest {
+    pub fn new(ttl: i64) -> LeaseGrantRequest {
+        LeaseGrantRequest {
+            ttl: Some(ttl.to_string()),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LeaseGrantResponse {
+    pub header: Option<ResponseHeader>,
+    #[serde(rename = "ID")]
+    id: Option<String>,
+    #[serde(rename = "TTL")]
+    ttl: Option<String>,
+    pub error: Option<String>,
+}
+
+impl LeaseGrantResponse {
+    pub fn new(id: i64, ttl: i64) -> LeaseGrantResponse {
+        LeaseGrantResponse {
+            header: None,
+            id: Some(id.to_string()),
+            ttl: Some(ttl.to_string()),
+            error: None,
+        }
+    }
+
+    pub fn id(&self) -> Option<i64> {
+        self.id.as_ref().and_then(|v| v.parse::<i64>().ok())
+    }
+
+    pub fn ttl(&self) -> Option<i64> {
+        self.ttl.as_ref().and_then(|v| v.parse::<i64>().ok())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LeaseRevokeRequest {
+    #[serde(rename = "ID")]
+    pub id: Option<String>,
+}
+
+impl LeaseRevokeRequest {
+    pub fn new(id: i64) -> LeaseRevokeRequest {
+        LeaseRevokeRequest { id: Some(id.to_string()) }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LeaseRevokeResponse {
+    pub header: Option<ResponseHeader>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LeaseKeepAliveRequest {
+    #[serde(rename = "ID")]
+    pub id: Option<String>,
+}
+
+impl LeaseKeepAliveRequest {
+    pub fn new(id: i64) -> LeaseKeepAliveRequest {
+        LeaseKeepAliveRequest { id: Some(id.to_string()) }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LeaseKeepAliveResponse {
+    pub header: Option<ResponseHeader>,
+    #[serde(rename = "ID")]
+    id: Option<String>,
+    #[serde(rename = "TTL")]
+    ttl: Option<String>,
+}
+
+impl LeaseKeepAliveResponse {
+    pub fn new(id: i64, ttl: i64) -> LeaseKeepAliveResponse {
+        LeaseKeepAliveResponse {
+            header: None,
+            id: Some(id.to_string()),
+            ttl: Some(ttl.to_string()),
+        }
+    }
+
+    pub fn id(&self) -> Option<i64> {
+        self.id.as_ref().and_then(|v| v.parse::<i64>().ok())
+    }
+
+    /// Remaining TTL in seconds. `etcd` omits this (i.e., zero) once the lease has expired.
+    pub fn ttl(&self) -> i64 {
+        self.ttl
+            .as_ref()
+            .map(|v| v.parse::<i64>().unwrap())
+            .unwrap_or(0)
+    }
+}
+
+/// Keep alive is a stream `RPC`, hence the wrapper.
+#[derive(Serialize, Deserialize)]
+pub struct LeaseKeepAliveStreamResponse {
+    pub result: Option<LeaseKeepAliveResponse>,
+    /// Set instead of `result` when the gateway reports a failure in the middle of the stream.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<StreamError>,
+}
+
+/// Request for the `v3lock` service. The lock is held for as long as `lease` is alive.
+#[derive(Serialize, Deserialize)]
+pub struct LockRequest {
+    pub name: Option<String>,
+    pub lease: Option<String>,
+}
+
+impl LockRequest {
+    pub fn new(name: &str, lease: i64) -> LockRequest {
+        LockRequest {
+            name: Some(base64::encode(name)),
+            lease: Some(lease.to_string()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LockResponse {
+    pub header: Option<ResponseHeader>,
+    key: Option<String>,
+}
+
+impl LockResponse {
+    pub fn new(key: &str) -> LockResponse {
+        LockResponse {
+            header: None,
+            key: Some(base64::encode(key)),
+        }
+    }
+
+    /// Key owning the lock, this is what must be passed to unlock.
+    pub fn key(&self) -> Option<String> {
+        match self.key {
+            Some(ref k) => {
+                base64::decode(&k).ok().map(
+                    |v| String::from_utf8_lossy(&v).into_owned(),
+                )
+            }
+            None => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UnlockRequest {
+    pub key: Option<String>,
+}
+
+impl UnlockRequest {
+    pub fn new(key: &str) -> UnlockRequest {
+        UnlockRequest { key: Some(base64::encode(key)) }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UnlockResponse {
+    pub header: Option<ResponseHeader>,
+}
+
+/// Identifies a campaign that won an election, handed back to `proclaim` and `resign`.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct LeaderKey {
+    pub name: Option<String>,
+    pub key: Option<String>,
+    pub rev: Option<String>,
+    pub lease: Option<String>,
+}
+
+impl LeaderKey {
+    /// Key created by the campaign.
+    pub fn key(&self) -> Option<String> {
+        match self.key {
+            Some(ref k) => {
+                base64::decode(&k).ok().map(
+                    |v| String::from_utf8_lossy(&v).into_owned(),
+                )
+            }
+            None => None,
+        }
+    }
+
+    /// Create revision of the campaign key.
+    pub fn rev(&self) -> i64 {
+        self.rev
+            .as_ref()
+            .map(|v| v.parse::<i64>().unwrap())
+            .unwrap_or(0)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CampaignRequest {
+    pub name: Option<String>,
+    pub lease: Option<String>,
+    pub value: Option<String>,
+}
+
+impl CampaignRequest {
+    pub fn new(name: &str, lease: i64, value: &str) -> CampaignRequest {
+        CampaignRequest {
+            name: Some(base64::encode(name)),
+            lease: Some(lease.to_string()),
+            value: Some(base64::encode(value)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CampaignResponse {
+    pub header: Option<ResponseHeader>,
+    pub leader: Option<LeaderKey>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ProclaimRequest {
+    pub leader: Option<LeaderKey>,
+    pub value: Option<String>,
+}
+
+impl ProclaimRequest {
+    pub fn new(leader: &LeaderKey, value: &str) -> ProclaimRequest {
+        ProclaimRequest {
+            leader: Some(leader.clone()),
+            value: Some(base64::encode(value)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ProclaimResponse {
+    pub header: Option<ResponseHeader>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LeaderRequest {
+    pub name: Option<String>,
+}
+
+impl LeaderRequest {
+    pub fn new(name: &str) -> LeaderRequest {
+        LeaderRequest { name: Some(base64::encode(name)) }
+    }
+}
+
+/// Response for both `LeaderRequest` and each update from observe.
+#[derive(Serialize, Deserialize)]
+pub struct LeaderResponse {
+    pub header: Option<ResponseHeader>,
+    pub kv: Option<KeyValue>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LeaderStreamResponse {
+    pub result: Option<LeaderResponse>,
+    /// Set instead of `result` when the gateway reports a failure in the middle of the stream.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<StreamError>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ResignRequest {
+    pub leader: Option<LeaderKey>,
+}
+
+impl ResignRequest {
+    pub fn new(leader: &LeaderKey) -> ResignRequest {
+        ResignRequest { leader: Some(leader.clone()) }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ResignResponse {
+    pub header: Option<ResponseHeader>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AuthenticateRequest {
+    pub name: Option<String>,
+    pub password: Option<String>,
+}
+
+impl AuthenticateRequest {
+    pub fn new(name: &str, password: &str) -> AuthenticateRequest {
+        AuthenticateRequest {
+            name: Some(String::from(name)),
+            password: Some(String::from(password)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AuthenticateResponse {
+    pub header: Option<ResponseHeader>,
+    /// Token to pass in the `Authorization` header of later requests.
+    pub token: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct MemberListRequest {}
+
+/// A member of the cluster, as reported by `MemberListResponse`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Member {
+    #[serde(rename = "ID")]
+    id: Option<String>,
+    /// Empty until the member has been started.
+    pub name: Option<String>,
+    #[serde(rename = "peerURLs")]
+    pub peer_urls: Option<Vec<String>>,
+    #[serde(rename = "clientURLs")]
+    pub client_urls: Option<Vec<String>>,
+    #[serde(rename = "isLearner")]
+    pub is_learner: Option<bool>,
+}
+
+impl Member {
+    pub fn new(id: u64, name: &str, client_url: &str) -> Member {
+        Member {
+            id: Some(id.to_string()),
+            name: Some(String::from(name)),
+            peer_urls: None,
+            client_urls: Some(vec![String::from(client_url)]),
+            is_learner: None,
+        }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+            .as_ref()
+            .map(|v| v.parse::<u64>().unwrap())
+            .unwrap_or(0)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MemberListResponse {
+    pub header: Option<ResponseHeader>,
+    pub members: Option<Vec<Member>>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct StatusRequest {}
+
+/// Status of the member that answered a `StatusRequest`.
+#[derive(Serialize, Deserialize, Default)]
+pub struct StatusResponse {
+    pub header: Option<ResponseHeader>,
+    /// Version of `etcd` the member runs.
+    pub version: Option<String>,
+    #[serde(rename = "dbSize")]
+    pub db_size: Option<String>,
+    /// ID of the member the answering member believes to be the leader.
+    pub leader: Option<String>,
+    #[serde(rename = "raftIndex")]
+    pub raft_index: Option<String>,
+    #[serde(rename = "raftTerm")]
+    pub raft_term: Option<String>,
+    pub errors: Option<Vec<String>>,
+}
+
+impl StatusResponse {
+    /// Size of the backend database in bytes.
+    pub fn db_size(&self) -> i64 {
+        self.db_size
+            .as_ref()
+            .map(|v| v.parse::<i64>().unwrap())
+            .unwrap_or(0)
+    }
+
+    pub fn leader(&self) -> u64 {
+        self.leader
+            .as_ref()
+            .map(|v| v.parse::<u64>().unwrap())
+            .unwrap_or(0)
+    }
+
+    /// ID of the member that answered.
+    pub fn member_id(&self) -> u64 {
+        self.header
+            .as_ref()
+            .and_then(|header| header.member_id.as_ref())
+            .map(|v| v.parse::<u64>().unwrap())
+            .unwrap_or(0)
+    }
+
+    pub fn raft_index(&self) -> u64 {
+        self.raft_index
+            .as_ref()
+            .map(|v| v.parse::<u64>().unwrap())
+            .unwrap_or(0)
+    }
+
+    pub fn raft_term(&self) -> u64 {
+        self.raft_term
+            .as_ref()
+            .map(|v| v.parse::<u64>().unwrap())
+            .unwrap_or(0)
+    }
 }
@@ -0,0 +1,189 @@
+use super::etcd_actions::{watch_closed, EtcdSession};
+use super::etcd_error::Result;
+use super::etcd_proto::*;
+use futures::TryStreamExt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A distributed FIFO queue. Items are stored as keys under a prefix and handed out in the order
+/// they were created, each to exactly one consumer.
+pub struct Queue {
+    session: EtcdSession,
+    prefix: String,
+}
+
+impl Queue {
+    /// Create a queue whose items live under `prefix`.
+    pub fn new(session: &EtcdSession, prefix: &str) -> Queue {
+        Queue {
+            session: session.clone(),
+            prefix: format!("{}/", prefix.trim_end_matches('/')),
+        }
+    }
+
+    /// Add `val` to the back of the queue.
+    pub async fn enqueue(&self, val: &str) -> Result<()> {
+        loop {
+            // Keys only need to be unique, ordering comes from their create revision.
+            let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+            let key = format!(
+                "{}{:020}{:09}",
+                self.prefix,
+                since_epoch.as_secs(),
+                since_epoch.subsec_nanos()
+            );
+            if put_new(&self.session, &key, val).await? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Remove and return the item at the front of the queue, waiting for one if it is empty.
+    pub async fn dequeue(&self) -> Result<String> {
+        let mut range_request = RangeRequest::new_for_prefix_with_sort(
+            &self.prefix,
+            SortOrder::ASCEND,
+            SortTarget::CREATE,
+        );
+        range_request.limit = Some(String::from("1"));
+        loop {
+            if let Some(val) = pop_first(&self.session, &self.prefix, &range_request).await? {
+                return Ok(val);
+            }
+        }
+    }
+}
+
+/// A distributed priority queue. Keys encode a zero-padded priority followed by a per-priority
+/// sequence number, so the lowest key is always the most urgent item. Lower priorities are
+/// dequeued first.
+pub struct PriorityQueue {
+    session: EtcdSession,
+    prefix: String,
+}
+
+impl PriorityQueue {
+    /// Create a priority queue whose items live under `prefix`.
+    pub fn new(session: &EtcdSession, prefix: &str) -> PriorityQueue {
+        PriorityQueue {
+            session: session.clone(),
+            prefix: format!("{}/", prefix.trim_end_matches('/')),
+        }
+    }
+
+    /// Add `val` with priority `priority`, behind every item already queued at that priority.
+    pub async fn enqueue(&self, val: &str, priority: u16) -> Result<()> {
+        let prefix = format!("{}{:05}/", self.prefix, priority);
+        let mut range_request =
+            RangeRequest::new_for_prefix_with_sort(&prefix, SortOrder::DESCEND, SortTarget::KEY);
+        range_request.keys_only = Some(true);
+        loop {
+            // Keys under the prefix that were not written by a queue sort anywhere, so skip them
+            // rather than taking the last key as the highest sequence number.
+            let resp = self.session.range_raw(&range_request).await?;
+            let next = resp
+                .kvs
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|kv| kv.key()?[prefix.len()..].parse::<u64>().ok())
+                .next()
+                .map_or(0, |seq| seq + 1);
+            // Concurrent producers may pick the same sequence number, only one of them gets to
+            // create the key and the others retry.
+            if put_new(&self.session, &format!("{}{:020}", prefix, next), val).await? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Remove and return the most urgent item, waiting for one if the queue is empty.
+    pub async fn dequeue(&self) -> Result<String> {
+        let mut range_request = RangeRequest::new_for_prefix_with_sort(
+            &self.prefix,
+            SortOrder::ASCEND,
+            SortTarget::KEY,
+        );
+        range_request.limit = Some(String::from("1"));
+        loop {
+            if let Some(val) = pop_first(&self.session, &self.prefix, &range_request).await? {
+                return Ok(val);
+            }
+        }
+    }
+}
+
+/// Create `key` with `val` if it does not exist yet, resolving to whether it was created.
+pub(crate) async fn put_new(session: &EtcdSession, key: &str, val: &str) -> Result<bool> {
+    let txn = TxnRequest::new(
+        vec![Compare::new_create_revision(key, CompareResult::EQUAL, 0)],
+        vec![RequestOp::Put(PutRequest::new(key, val))],
+        vec![],
+    );
+    Ok(session.txn(&txn).await?.succeeded())
+}
+
+/// Delete the first key returned by `range_request` provided nobody modified or removed it in
+/// the meantime. If the range is empty, wait for something to be put under `prefix` instead.
+/// Resolves to the value of the deleted key, or `None` if the caller should retry.
+pub(crate) async fn pop_first(
+    session: &EtcdSession,
+    prefix: &str,
+    range_request: &RangeRequest,
+) -> Result<Option<String>> {
+    let resp = session.range_raw(range_request).await?;
+    let rev = resp.header.as_ref().unwrap().rev();
+    match resp.kvs.as_ref().and_then(|kvs| kvs.first()) {
+        Some(kv) => {
+            let key = kv.key().unwrap();
+            let txn = TxnRequest::new(
+                vec![Compare::new_mod_revision(
+                    &key,
+                    CompareResult::EQUAL,
+                    kv.mod_rev(),
+                )],
+                vec![RequestOp::DeleteRange(DeleteRangeRequest::new(&key))],
+                vec![],
+            );
+            // Another consumer won the race if the comparison failed.
+            if session.txn(&txn).await?.succeeded() {
+                Ok(Some(kv.value().unwrap_or_default()))
+            } else {
+                Ok(None)
+            }
+        }
+        None => {
+            wait_put_pfx(session, prefix, rev + 1).await?;
+            Ok(None)
+        }
+    }
+}
+
+/// Resolves once `key` is put at or after revision `rev`. Fails if the watch ends first, e.g.,
+/// after `EtcdSession::cancel_watches`.
+pub(crate) async fn wait_put(session: &EtcdSession, key: &str, rev: i64) -> Result<()> {
+    wait_put_with(session, WatchCreateRequest::new_for_key(key), rev).await
+}
+
+/// Resolves once a key under `prefix` is put at or after revision `rev`. Fails if the watch ends
+/// first, e.g., after `EtcdSession::cancel_watches`.
+pub(crate) async fn wait_put_pfx(session: &EtcdSession, prefix: &str, rev: i64) -> Result<()> {
+    wait_put_with(session, WatchCreateRequest::new_for_prefix(prefix), rev).await
+}
+
+async fn wait_put_with(
+    session: &EtcdSession,
+    create_request: WatchCreateRequest,
+    rev: i64,
+) -> Result<()> {
+    let create_request = WatchCreateRequest {
+        start_revision: Some(rev.to_string()),
+        filters: Some(vec![FilterType::NODELETE]),
+        ..create_request
+    };
+    let mut stream = session.watch_with(create_request).await?;
+    while let Some(resp) = stream.try_next().await? {
+        if resp.events.as_ref().is_some_and(|evs| !evs.is_empty()) {
+            return Ok(());
+        }
+    }
+    Err(watch_closed())
+}
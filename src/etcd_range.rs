@@ -0,0 +1,134 @@
+use super::etcd_proto::*;
+use base64;
+
+/// Builder for range reads through `EtcdSession::range`, exposing every option of a
+/// `RangeRequest` without having to deal with its wire encoding.
+#[derive(Clone)]
+pub struct RangeOptions {
+    request: RangeRequest,
+}
+
+impl RangeOptions {
+    /// Read a single key.
+    pub fn key(key: &str) -> RangeOptions {
+        RangeOptions {
+            request: RangeRequest::new(key),
+        }
+    }
+
+    /// Read every key starting with `prefix`.
+    pub fn prefix(prefix: &str) -> RangeOptions {
+        RangeOptions {
+            request: RangeRequest::new_for_prefix(prefix),
+        }
+    }
+
+    /// Read every key in `[start, end)`.
+    pub fn interval(start: &str, end: &str) -> RangeOptions {
+        RangeOptions {
+            request: RangeRequest {
+                range_end: Some(base64::encode(end)),
+                ..RangeRequest::new(start)
+            },
+        }
+    }
+
+    /// Read every key greater than or equal to `start`.
+    pub fn from_key(start: &str) -> RangeOptions {
+        RangeOptions {
+            request: RangeRequest {
+                range_end: Some(base64::encode(&[0u8][..])),
+                ..RangeRequest::new(start)
+            },
+        }
+    }
+
+    /// Return at most `limit` keys, zero means no limit.
+    pub fn limit(mut self, limit: usize) -> RangeOptions {
+        self.request.limit = Some(limit.to_string());
+        self
+    }
+
+    /// Read the store as it was at `revision`, which must not have been compacted.
+    pub fn revision(mut self, revision: i64) -> RangeOptions {
+        self.request.revision = Some(revision.to_string());
+        self
+    }
+
+    pub fn sort(mut self, order: SortOrder, target: SortTarget) -> RangeOptions {
+        self.request.sort_order = Some(order);
+        self.request.sort_target = Some(target);
+        self
+    }
+
+    /// Serve the read from the local member, which is faster but may return stale data.
+    pub fn serializable(mut self) -> RangeOptions {
+        self.request.serializable = Some(true);
+        self
+    }
+
+    /// Return keys without their values.
+    pub fn keys_only(mut self) -> RangeOptions {
+        self.request.keys_only = Some(true);
+        self
+    }
+
+    /// Return only the number of keys in the range.
+    pub fn count_only(mut self) -> RangeOptions {
+        self.request.count_only = Some(true);
+        self
+    }
+
+    pub fn min_mod_revision(mut self, revision: i64) -> RangeOptions {
+        self.request.min_mod_revision = Some(revision.to_string());
+        self
+    }
+
+    pub fn max_mod_revision(mut self, revision: i64) -> RangeOptions {
+        self.request.max_mod_revision = Some(revision.to_string());
+        self
+    }
+
+    pub fn min_create_revision(mut self, revision: i64) -> RangeOptions {
+        self.request.min_create_revision = Some(revision.to_string());
+        self
+    }
+
+    pub fn max_create_revision(mut self, revision: i64) -> RangeOptions {
+        self.request.max_create_revision = Some(revision.to_string());
+        self
+    }
+
+    /// The `RangeRequest` these options describe.
+    pub fn request(&self) -> &RangeRequest {
+        &self.request
+    }
+}
+
+/// Result of `EtcdSession::range`, with the defaults `etcd` omits from responses filled in.
+pub struct RangeResult {
+    pub header: Option<ResponseHeader>,
+    pub kvs: Vec<KeyValue>,
+    /// Whether there are more keys in the range than were returned because of the limit.
+    pub more: bool,
+    /// Number of keys in the range, regardless of the limit.
+    pub count: usize,
+}
+
+impl RangeResult {
+    /// Store revision the range was read at.
+    pub fn rev(&self) -> i64 {
+        self.header.as_ref().map_or(0, |header| header.rev())
+    }
+}
+
+impl From<RangeResponse> for RangeResult {
+    fn from(resp: RangeResponse) -> RangeResult {
+        RangeResult {
+            count: resp.count(),
+            more: resp.more.unwrap_or(false),
+            kvs: resp.kvs.unwrap_or_default(),
+            header: resp.header,
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use super::etcd_actions::EtcdSession;
+use super::etcd_concurrency::{unique_key, HeldLease, LeaseMode, Session};
+use super::etcd_error::Result;
+use super::etcd_lock::{abandon, claim_key, spawn_abandon};
+use super::etcd_proto::*;
+
+/// A distributed counting semaphore with a fixed number of permits. Holders create lease-attached
+/// keys under a common prefix, and the `permits` keys with the lowest create revisions hold the
+/// permits. Since keys are attached to leases, the permit of a crashed holder is freed once its
+/// lease expires.
+pub struct Semaphore {
+    session: EtcdSession,
+    prefix: String,
+    permits: usize,
+    lease: LeaseMode,
+}
+
+impl Semaphore {
+    /// Create a semaphore with `permits` permits whose holders live under `prefix`, each attached
+    /// to its own lease of `ttl` seconds.
+    pub fn new(session: &EtcdSession, prefix: &str, permits: usize, ttl: i64) -> Semaphore {
+        Semaphore {
+            session: session.clone(),
+            prefix: format!("{}/", prefix.trim_end_matches('/')),
+            permits,
+            lease: LeaseMode::PerHolder(ttl),
+        }
+    }
+
+    /// Create a semaphore with `permits` permits whose holders live under `prefix`, attached to
+    /// the lease of `session`.
+    pub fn with_session(session: &Session, prefix: &str, permits: usize) -> Semaphore {
+        Semaphore {
+            session: session.client().clone(),
+            prefix: format!("{}/", prefix.trim_end_matches('/')),
+            permits,
+            lease: LeaseMode::from_session(session),
+        }
+    }
+
+    /// Acquire a permit, resolving once fewer than `permits` earlier holders remain.
+    pub async fn acquire(&self) -> Result<Permit> {
+        let lease = self.lease.acquire(&self.session).await?;
+        let key = unique_key(&self.prefix, lease.id());
+        let acquired = async {
+            let rev = claim_key(&self.session, &key, lease.id()).await?;
+            wait_turn(&self.session, &self.prefix, rev, self.permits).await
+        };
+        match acquired.await {
+            Ok(()) => Ok(Permit {
+                session: self.session.clone(),
+                key,
+                lease,
+                released: false,
+            }),
+            Err(err) => {
+                let _ = abandon(&self.session, &key, lease.id(), lease.is_owned()).await;
+                Err(err)
+            }
+        }
+    }
+}
+
+/// Resolves once the key created at `rev` is among the `permits` oldest keys under `prefix`.
+async fn wait_turn(session: &EtcdSession, prefix: &str, rev: i64, permits: usize) -> Result<()> {
+    let mut range_request =
+        RangeRequest::new_for_prefix_with_sort(prefix, SortOrder::ASCEND, SortTarget::CREATE);
+    range_request.limit = Some(permits.to_string());
+    range_request.keys_only = Some(true);
+    loop {
+        let resp = session.range_raw(&range_request).await?;
+        let holding = resp
+            .kvs
+            .as_ref()
+            .is_some_and(|kvs| kvs.iter().any(|kv| kv.create_rev() == rev));
+        if holding {
+            return Ok(());
+        }
+        // Any earlier holder leaving moves us up, so check again after every deletion.
+        let rev = resp.header.as_ref().unwrap().rev();
+        session.wait_delete_pfx(prefix, rev + 1).await?;
+    }
+}
+
+/// A permit held through `Semaphore`. The permit is released when `release` is called or this is
+/// dropped.
+pub struct Permit {
+    session: EtcdSession,
+    key: String,
+    lease: HeldLease,
+    released: bool,
+}
+
+impl Permit {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn lease_id(&self) -> i64 {
+        self.lease.id()
+    }
+
+    /// Release the permit, and its lease unless it belongs to a `Session`.
+    pub async fn release(mut self) -> Result<()> {
+        self.released = true;
+        abandon(
+            &self.session,
+            &self.key,
+            self.lease.id(),
+            self.lease.is_owned(),
+        )
+        .await
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if !self.released {
+            spawn_abandon(&self.session, &self.key, &self.lease);
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use super::etcd_actions::EtcdSession;
+use super::etcd_error::Result;
+use super::etcd_proto::*;
+use futures::Future;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+/// Isolation levels for `Stm`, matching those of Go's `concurrency.STM`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Isolation {
+    /// Every read observes the store at the revision of the first read, so the transaction sees
+    /// a consistent snapshot.
+    Serializable,
+    /// Reads observe the latest revision, but the same key always reads the same value within
+    /// one attempt.
+    RepeatableReads,
+}
+
+/// Software transactional memory on top of `etcd` transactions. A closure reads and writes keys
+/// through a `StmContext`, which records the revision of every read and buffers every write. The
+/// writes are committed in a single transaction guarded on none of the read keys having changed,
+/// and the closure is run again whenever that guard fails.
+pub struct Stm {
+    session: EtcdSession,
+    isolation: Isolation,
+}
+
+impl Stm {
+    pub fn new(session: &EtcdSession, isolation: Isolation) -> Stm {
+        Stm {
+            session: session.clone(),
+            isolation,
+        }
+    }
+
+    /// Run `apply` until its writes commit without conflicts, resolving to the result of the
+    /// attempt that committed. `apply` may run several times, so it should have no side effects
+    /// besides those made through the context.
+    pub async fn run<F, Fut, R>(&self, mut apply: F) -> Result<R>
+    where
+        F: FnMut(StmContext) -> Fut,
+        Fut: Future<Output = Result<R>>,
+    {
+        loop {
+            let context = StmContext::new(&self.session, self.isolation);
+            let result = apply(context.clone()).await?;
+            if self
+                .session
+                .txn(&context.commit_request())
+                .await?
+                .succeeded()
+            {
+                return Ok(result);
+            }
+        }
+    }
+}
+
+struct StmState {
+    /// Revision every read is pinned to under `Isolation::Serializable`.
+    rev: Option<i64>,
+    /// Value and mod revision of every key read, a mod revision of zero means it did not exist.
+    reads: BTreeMap<String, (Option<String>, i64)>,
+    /// Buffered writes, `None` marks a deletion.
+    writes: BTreeMap<String, Option<String>>,
+}
+
+/// The view of the store a single `Stm` attempt works against. Cloning is cheap and every clone
+/// shares the same read and write sets.
+#[derive(Clone)]
+pub struct StmContext {
+    session: EtcdSession,
+    isolation: Isolation,
+    state: Arc<Mutex<StmState>>,
+}
+
+impl StmContext {
+    fn new(session: &EtcdSession, isolation: Isolation) -> StmContext {
+        StmContext {
+            session: session.clone(),
+            isolation,
+            state: Arc::new(Mutex::new(StmState {
+                rev: None,
+                reads: BTreeMap::new(),
+                writes: BTreeMap::new(),
+            })),
+        }
+    }
+
+    /// Read `key`, observing any write buffered earlier in this attempt.
+    pub async fn get(&self, key: &str) -> Result<Option<String>> {
+        let mut range_request = RangeRequest::new(key);
+        {
+            let state = self.state.lock().unwrap();
+            if let Some(val) = state.writes.get(key) {
+                return Ok(val.clone());
+            }
+            if let Some((val, _)) = state.reads.get(key) {
+                return Ok(val.clone());
+            }
+            if self.isolation == Isolation::Serializable {
+                range_request.revision = state.rev.map(|rev| rev.to_string());
+            }
+        }
+        let resp = self.session.range_raw(&range_request).await?;
+        let mut state = self.state.lock().unwrap();
+        if state.rev.is_none() {
+            state.rev = Some(resp.header.as_ref().unwrap().rev());
+        }
+        let (val, rev) = match resp.kvs.as_ref().and_then(|kvs| kvs.first()) {
+            Some(kv) => (kv.value(), kv.mod_rev()),
+            None => (None, 0),
+        };
+        Ok(state
+            .reads
+            .entry(String::from(key))
+            .or_insert((val, rev))
+            .0
+            .clone())
+    }
+
+    /// Buffer a write of `val` to `key`.
+    pub fn put(&self, key: &str, val: &str) {
+        self.state
+            .lock()
+            .unwrap()
+            .writes
+            .insert(String::from(key), Some(String::from(val)));
+    }
+
+    /// Buffer the deletion of `key`.
+    pub fn delete(&self, key: &str) {
+        self.state
+            .lock()
+            .unwrap()
+            .writes
+            .insert(String::from(key), None);
+    }
+
+    fn commit_request(&self) -> TxnRequest {
+        let state = self.state.lock().unwrap();
+        // Every read key must still be at the revision it was read at, which for a key that did
+        // not exist is zero, so deleting or recreating it counts as a change.
+        let mut compare: Vec<_> = state
+            .reads
+            .iter()
+            .map(|(key, &(_, rev))| Compare::new_mod_revision(key, CompareResult::EQUAL, rev))
+            .collect();
+        // Under serializable isolation, keys written without being read must not have changed
+        // since the snapshot either.
+        if let (Isolation::Serializable, Some(rev)) = (self.isolation, state.rev) {
+            compare.extend(
+                state
+                    .writes
+                    .keys()
+                    .filter(|key| !state.reads.contains_key(*key))
+                    .map(|key| Compare::new_mod_revision(key, CompareResult::LESS, rev + 1)),
+            );
+        }
+        let success = state
+            .writes
+            .iter()
+            .map(|(key, val)| match *val {
+                Some(ref val) => RequestOp::Put(PutRequest::new(key, val)),
+                None => RequestOp::DeleteRange(DeleteRangeRequest::new(key)),
+            })
+            .collect();
+        TxnRequest::new(compare, success, vec![])
+    }
+}
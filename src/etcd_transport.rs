@@ -0,0 +1,301 @@
+//! How an `EtcdSession` reaches the gateway. Requests go through a `Transport`, which is plain
+//! HTTP by default. A `Recorder` captures the exchanges of another transport into a `Cassette`
+//! that can be saved as JSON, and a `Replayer` later serves those exchanges back without an
+//! `etcd`, so tests run deterministically.
+//!
+//! Recorded requests are matched on their endpoint and JSON body only; the authorization token is
+//! neither recorded nor compared. Requests whose bodies change from one run to the next, e.g.,
+//! queue keys derived from the clock, cannot be replayed.
+
+use super::etcd_error::{Error, Result};
+use bytes::Bytes;
+use futures::future::{self, BoxFuture};
+use futures::stream::{self, BoxStream};
+use futures::{FutureExt, StreamExt, TryStreamExt};
+use http_body_util::{BodyStream, Full};
+use hyper::StatusCode;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// The answer to a request: its status and its body, as the chunks it arrived in.
+pub struct Reply {
+    pub status: StatusCode,
+    pub body: BoxStream<'static, Result<Bytes>>,
+}
+
+/// Carries the requests of an `EtcdSession` to the gateway.
+pub trait Transport: Send + Sync {
+    /// POST `body` to `uri`, sending `token` as the `Authorization` header if there is one.
+    fn post(
+        &self,
+        uri: &str,
+        token: Option<&str>,
+        body: Bytes,
+    ) -> BoxFuture<'static, Result<Reply>>;
+}
+
+/// The default transport, talking HTTP/1 to the gateway over a pooled connection.
+#[derive(Clone)]
+pub struct HttpTransport {
+    client: Client<HttpConnector, Full<Bytes>>,
+}
+
+impl HttpTransport {
+    pub fn new() -> HttpTransport {
+        HttpTransport {
+            client: Client::builder(TokioExecutor::new()).build_http(),
+        }
+    }
+}
+
+impl Default for HttpTransport {
+    fn default() -> HttpTransport {
+        HttpTransport::new()
+    }
+}
+
+impl Transport for HttpTransport {
+    fn post(
+        &self,
+        uri: &str,
+        token: Option<&str>,
+        body: Bytes,
+    ) -> BoxFuture<'static, Result<Reply>> {
+        let mut request = hyper::Request::post(uri);
+        if let Some(token) = token {
+            request = request.header(hyper::header::AUTHORIZATION, token);
+        }
+        let request = match request.body(Full::new(body)) {
+            Ok(request) => request,
+            Err(err) => return future::err(Error::Http(Box::new(err))).boxed(),
+        };
+        let client = self.client.clone();
+        async move {
+            let res = client.request(request).await?;
+            let status = res.status();
+            let body = BodyStream::new(res.into_body())
+                .map_err(Error::from)
+                .try_filter_map(|frame| future::ok(frame.into_data().ok()))
+                .boxed();
+            Ok(Reply { status, body })
+        }
+        .boxed()
+    }
+}
+
+/// A set of recorded exchanges with the gateway.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Cassette {
+    pub interactions: Vec<Interaction>,
+}
+
+impl Cassette {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Cassette> {
+        Ok(serde_json::from_slice(&fs::read(path)?)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        Ok(fs::write(path, serde_json::to_vec_pretty(self)?)?)
+    }
+}
+
+/// A single request and the response it got.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Interaction {
+    /// The path the request was sent to, e.g., `/v3alpha/kv/put`.
+    pub endpoint: String,
+    pub request: Value,
+    pub status: u16,
+    /// The response body in the chunks it arrived in, e.g., one message per chunk for a watch.
+    pub chunks: Vec<String>,
+    /// Whether the response ended while it was recorded. A response that did not, such as a watch
+    /// that was still open, stays open after its last chunk when replayed.
+    pub complete: bool,
+}
+
+/// Wraps another transport and records every exchange going through it.
+pub struct Recorder<T> {
+    inner: T,
+    cassette: Arc<Mutex<Cassette>>,
+}
+
+impl<T: Transport> Recorder<T> {
+    pub fn new(inner: T) -> Recorder<T> {
+        Recorder {
+            inner,
+            cassette: Arc::new(Mutex::new(Cassette::default())),
+        }
+    }
+
+    /// A handle to the recording, which stays usable after the recorder has been handed to a
+    /// session.
+    pub fn recording(&self) -> Recording {
+        Recording {
+            cassette: self.cassette.clone(),
+        }
+    }
+}
+
+impl<T: Transport> Transport for Recorder<T> {
+    fn post(
+        &self,
+        uri: &str,
+        token: Option<&str>,
+        body: Bytes,
+    ) -> BoxFuture<'static, Result<Reply>> {
+        let endpoint = endpoint_of(uri);
+        let request = request_of(&body);
+        let reply = self.inner.post(uri, token, body);
+        let cassette = self.cassette.clone();
+        async move {
+            let reply = reply.await?;
+            let index = {
+                let mut cassette = cassette.lock().unwrap();
+                cassette.interactions.push(Interaction {
+                    endpoint,
+                    request,
+                    status: reply.status.as_u16(),
+                    chunks: Vec::new(),
+                    complete: false,
+                });
+                cassette.interactions.len() - 1
+            };
+            let body = stream::unfold(reply.body, move |mut body| {
+                let cassette = cassette.clone();
+                async move {
+                    let chunk = body.next().await;
+                    let mut cassette = cassette.lock().unwrap();
+                    let interaction = &mut cassette.interactions[index];
+                    match chunk {
+                        Some(Ok(ref data)) => {
+                            interaction
+                                .chunks
+                                .push(String::from_utf8_lossy(data).into_owned());
+                        }
+                        Some(Err(_)) => {}
+                        None => {
+                            interaction.complete = true;
+                            return None;
+                        }
+                    }
+                    chunk.map(|chunk| (chunk, body))
+                }
+            });
+            Ok(Reply {
+                status: reply.status,
+                body: body.boxed(),
+            })
+        }
+        .boxed()
+    }
+}
+
+/// The exchanges a `Recorder` has captured so far.
+#[derive(Clone)]
+pub struct Recording {
+    cassette: Arc<Mutex<Cassette>>,
+}
+
+impl Recording {
+    pub fn cassette(&self) -> Cassette {
+        self.cassette.lock().unwrap().clone()
+    }
+
+    /// Write the exchanges captured so far to `path`, including the chunks of any response that
+    /// is still streaming.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        self.cassette().save(path)
+    }
+}
+
+/// Serves the exchanges of a cassette instead of talking to the gateway. Each recorded exchange
+/// is served once, to the first request with the same endpoint and body; a request that matches
+/// none fails with `Error::Unrecorded` and is remembered, so a test can assert on it.
+#[derive(Clone)]
+pub struct Replayer {
+    state: Arc<Mutex<Replay>>,
+}
+
+struct Replay {
+    pending: Vec<Interaction>,
+    unexpected: Vec<String>,
+}
+
+impl Replayer {
+    pub fn new(cassette: Cassette) -> Replayer {
+        Replayer {
+            state: Arc::new(Mutex::new(Replay {
+                pending: cassette.interactions,
+                unexpected: Vec::new(),
+            })),
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Replayer> {
+        Ok(Replayer::new(Cassette::load(path)?))
+    }
+
+    /// The requests that matched no recorded exchange, as their endpoint followed by their body.
+    pub fn unexpected(&self) -> Vec<String> {
+        self.state.lock().unwrap().unexpected.clone()
+    }
+
+    /// Number of recorded exchanges that have not been served yet.
+    pub fn remaining(&self) -> usize {
+        self.state.lock().unwrap().pending.len()
+    }
+}
+
+impl Transport for Replayer {
+    fn post(
+        &self,
+        uri: &str,
+        _token: Option<&str>,
+        body: Bytes,
+    ) -> BoxFuture<'static, Result<Reply>> {
+        let endpoint = endpoint_of(uri);
+        let request = request_of(&body);
+        let mut state = self.state.lock().unwrap();
+        let found = state
+            .pending
+            .iter()
+            .position(|i| i.endpoint == endpoint && i.request == request);
+        let interaction = match found {
+            Some(index) => state.pending.remove(index),
+            None => {
+                let description = format!("{} {}", endpoint, request);
+                state.unexpected.push(description.clone());
+                return future::err(Error::Unrecorded(description)).boxed();
+            }
+        };
+        let status = match StatusCode::from_u16(interaction.status) {
+            Ok(status) => status,
+            Err(err) => return future::err(Error::Http(Box::new(err))).boxed(),
+        };
+        let chunks = stream::iter(interaction.chunks.into_iter().map(|c| Ok(Bytes::from(c))));
+        let body = if interaction.complete {
+            chunks.boxed()
+        } else {
+            chunks.chain(stream::pending()).boxed()
+        };
+        future::ok(Reply { status, body }).boxed()
+    }
+}
+
+/// The path of `uri`, so recordings do not depend on where the gateway was running.
+fn endpoint_of(uri: &str) -> String {
+    match uri.parse::<hyper::Uri>() {
+        Ok(uri) => String::from(uri.path()),
+        Err(_) => String::from(uri),
+    }
+}
+
+fn request_of(body: &[u8]) -> Value {
+    serde_json::from_slice(body)
+        .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(body).into_owned()))
+}
@@ -1,36 +1,74 @@
 #![deny(warnings)]
-extern crate serde;
-extern crate serde_json;
-extern crate base64;
-extern crate futures;
-extern crate hyper;
-extern crate tokio_core;
-
 #[macro_use]
 extern crate serde_derive;
-pub mod etcd_proto;
+#[cfg(feature = "blocking")]
+pub mod blocking;
 pub mod etcd_actions;
+pub mod etcd_barrier;
+pub mod etcd_codec;
+pub mod etcd_concurrency;
+pub mod etcd_counter;
+pub mod etcd_discovery;
+pub mod etcd_election;
+pub mod etcd_error;
+pub mod etcd_export;
+pub mod etcd_kv;
+pub mod etcd_lock;
+pub mod etcd_memory;
+pub mod etcd_mirror;
+pub mod etcd_namespace;
+pub mod etcd_proto;
+pub mod etcd_queue;
+pub mod etcd_range;
+pub mod etcd_semaphore;
+pub mod etcd_stm;
+pub mod etcd_transport;
+#[cfg(feature = "test-server")]
+pub mod test_server;
 
 //pub use self::etcd_proto::*;
 
 #[cfg(test)]
 mod tests {
-    use super::*;
     use super::etcd_proto::*;
-    use serde_json;
-    use hyper;
-    use tokio_core;
-    use futures::Future;
-    use futures::stream::Stream;
+    use super::*;
+    use bytes::Bytes;
+    use futures::{StreamExt, TryStreamExt};
+    use http_body_util::{BodyExt, BodyStream, Full};
+    use hyper_util::client::legacy::Client;
+    use hyper_util::rt::TokioExecutor;
     use std::str;
-    use std::io;
+    use std::time::Duration;
+
+    /// The gateway tests run against: a fake one started for the test with the `test-server`
+    /// feature, otherwise a live `etcd` on localhost.
+    #[cfg(feature = "test-server")]
+    fn etcd() -> test_server::TestServer {
+        test_server::TestServer::start().unwrap()
+    }
+
+    #[cfg(not(feature = "test-server"))]
+    fn etcd() -> LiveEtcd {
+        LiveEtcd
+    }
+
+    #[cfg(not(feature = "test-server"))]
+    struct LiveEtcd;
+
+    #[cfg(not(feature = "test-server"))]
+    impl LiveEtcd {
+        fn uri(&self) -> String {
+            String::from("http://localhost:2379")
+        }
+    }
+
     #[test]
     fn basic_test() {
         let req = PutRequest::new("hello", "world 22");
         let json = serde_json::to_string(&req).unwrap();
         println!("{}", json);
-        let json = serde_json::to_string(&PutRequest::new_with_previous("hello", "world 23"))
-            .unwrap();
+        let json =
+            serde_json::to_string(&PutRequest::new_with_previous("hello", "world 23")).unwrap();
         println!("{}", json);
         let resp_json = r#"{"header":{"cluster_id":"14841639068965178418",
                                       "member_id":"10276657743932975437",
@@ -58,215 +96,1160 @@ mod tests {
             "hello",
             SortOrder::ASCEND,
             SortTarget::VALUE,
-        )).unwrap();
+        ))
+        .unwrap();
         println!("{}", json_range);
 
         let json_watch = serde_json::to_string(&WatchRequest::new_create_request(
             WatchCreateRequest::new_for_key("hello"),
-        )).unwrap();
+        ))
+        .unwrap();
         println!("{}", json_watch);
+
+        let json_txn = serde_json::to_string(&TxnRequest::new(
+            vec![Compare::new_create_revision(
+                "hello",
+                CompareResult::EQUAL,
+                0,
+            )],
+            vec![RequestOp::Put(PutRequest::new("hello", "world 24"))],
+            vec![RequestOp::Range(RangeRequest::new("hello"))],
+        ))
+        .unwrap();
+        println!("{}", json_txn);
+        let resp_json = r#"{"header":{"revision":"7"},
+                          "succeeded":true,
+                          "responses":[{"response_put":{"header":{"revision":"7"}}}]}"#;
+        let parsed: TxnResponse = serde_json::from_str(resp_json).unwrap();
+        assert!(parsed.succeeded());
+        assert_eq!(parsed.header.unwrap().rev(), 7);
     }
 
     #[test]
-    fn connected_test() {
-        let put_uri = "http://localhost:2379/v3alpha/kv/put"
-            .parse::<hyper::Uri>()
-            .unwrap();
-        let range_uri = "http://localhost:2379/v3alpha/kv/range"
-            .parse::<hyper::Uri>()
-            .unwrap();
-        let watch_uri = "http://localhost:2379/v3alpha/watch"
-            .parse::<hyper::Uri>()
-            .unwrap();
-        let mut core = tokio_core::reactor::Core::new().unwrap();
-        let handle = core.handle();
-        let client = hyper::Client::new(&handle);
-        let mut put_request = hyper::Request::new(hyper::Method::Post, put_uri);
-        put_request.set_body(
+    fn prefix_range_end_test() {
+        assert_eq!(prefix_range_end(b"abc"), b"abd");
+        assert_eq!(prefix_range_end(b"a\xff\xff"), b"b");
+        assert_eq!(prefix_range_end(b"\xff"), b"\0");
+        assert_eq!(prefix_range_end(b""), b"\0");
+        let req = RangeRequest::new_for_prefix("");
+        assert_eq!(req.key, Some(base64::encode("\0")));
+        assert_eq!(req.range_end, Some(base64::encode("\0")));
+    }
+
+    #[tokio::test]
+    async fn connected_test() {
+        let etcd = etcd();
+        let client: Client<_, Full<Bytes>> = Client::builder(TokioExecutor::new()).build_http();
+        let post = |uri: &str, body: String| {
+            hyper::Request::post(uri)
+                .body(Full::new(Bytes::from(body)))
+                .unwrap()
+        };
+        let put_request = post(
+            &format!("{}/v3alpha/kv/put", etcd.uri()),
             serde_json::to_string(&PutRequest::new("hello", "world 3333")).unwrap(),
         );
-        let work = client
-            .request(put_request)
-            .and_then(|res| {
-                println!("Response: {}", res.status());
-                res.body().concat2().and_then(|body: hyper::Chunk| {
-                    let v: PutResponse = serde_json::from_slice(&body).unwrap();
-                    println!(
-                        "Cluster ID for put is {}",
-                        v.header.unwrap().cluster_id.unwrap()
-                    );
-                    Ok(())
-                })
-            })
-            .and_then(|_| {
-                let mut range_request = hyper::Request::new(hyper::Method::Post, range_uri);
-                range_request.set_body(serde_json::to_string(&RangeRequest::new("hello")).unwrap());
-                client.request(range_request)
-            })
-            .and_then(|res| {
-                println!("Response: {}", res.status());
-                res.body().concat2().and_then(|body: hyper::Chunk| {
-                    let v: RangeResponse = serde_json::from_slice(&body).unwrap();
-                    println!("Returned {} value(s)", v.count());
-                    assert!(v.count() == 0 || v.kvs.as_ref().unwrap().len() == v.count());
-                    assert!(v.count() == 1);
-                    for kv in v.kvs.as_ref().unwrap() {
-                        println!(r#""{}" --> "{}""#, kv.key().unwrap(), kv.value().unwrap());
-                    }
-                    Ok(v.kvs.unwrap())
-                })
-            })
-            .and_then(|kvs| {
-                for kv in &kvs {
-                    println!(r#""{}" --> "{}""#, kv.key().unwrap(), kv.value().unwrap());
-                }
-                Ok(())
-            });
-        core.run(work).unwrap();
+        let res = client.request(put_request).await.unwrap();
+        println!("Response: {}", res.status());
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        let v: PutResponse = serde_json::from_slice(&body).unwrap();
+        println!(
+            "Cluster ID for put is {}",
+            v.header.unwrap().cluster_id.unwrap()
+        );
+
+        let range_request = post(
+            &format!("{}/v3alpha/kv/range", etcd.uri()),
+            serde_json::to_string(&RangeRequest::new("hello")).unwrap(),
+        );
+        let res = client.request(range_request).await.unwrap();
+        println!("Response: {}", res.status());
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        let v: RangeResponse = serde_json::from_slice(&body).unwrap();
+        println!("Returned {} value(s)", v.count());
+        assert!(v.count() == 0 || v.kvs.as_ref().unwrap().len() == v.count());
+        assert!(v.count() == 1);
+        for kv in v.kvs.as_ref().unwrap() {
+            println!(r#""{}" --> "{}""#, kv.key().unwrap(), kv.value().unwrap());
+        }
 
-        let mut watch_request = hyper::Request::new(hyper::Method::Post, watch_uri);
-        watch_request.set_body(
+        let watch_request = post(
+            &format!("{}/v3alpha/watch", etcd.uri()),
             serde_json::to_string(&WatchRequest::new_create_request(
                 WatchCreateRequest::new_for_key("hello"),
-            )).unwrap(),
+            ))
+            .unwrap(),
         );
-        let work = client.request(watch_request).and_then(|res| {
-            println!("Response: {}", res.status());
-            res.body().for_each(|body: hyper::Chunk| {
-                let outer: WatchStreamResponse = serde_json::from_slice(&body).unwrap();
-                let v = outer.result.as_ref().unwrap();
-                if let Some(created) = v.created {
-                    println!("Watch created {}", created);
-                } else if let Some(ref events) = v.events {
-                    for event in events {
-                        println!("Body is {}", str::from_utf8(&body).unwrap());
-                        println!("Event type {:?}", event.event_type());
-                        println!("Key {}", event.kv.as_ref().unwrap().key().unwrap());
-                    }
-                };
-                Err(hyper::Error::Io(
-                    io::Error::new(io::ErrorKind::TimedOut, "Done"),
-                ))
-            })
-        });
-        match core.run(work) {
-            Ok(_) => panic!("Should not return OK"),
-            Err(hyper::Error::Io(err)) => {
-                assert!(
-                    err.kind() == io::ErrorKind::TimedOut,
-                    "IO error, but not a timeout"
-                )
+        let res = client.request(watch_request).await.unwrap();
+        println!("Response: {}", res.status());
+        // The first message acknowledges the watch, stop reading after it.
+        let mut body = BodyStream::new(res.into_body());
+        let frame = body.next().await.unwrap().unwrap();
+        let chunk = frame.into_data().unwrap();
+        let outer: WatchStreamResponse = serde_json::from_slice(&chunk).unwrap();
+        let v = outer.result.as_ref().unwrap();
+        if let Some(created) = v.created {
+            println!("Watch created {}", created);
+        } else if let Some(ref events) = v.events {
+            for event in events {
+                println!("Body is {}", str::from_utf8(&chunk).unwrap());
+                println!("Event type {:?}", event.event_type());
+                println!("Key {}", event.kv.as_ref().unwrap().key().unwrap());
             }
-            _ => panic!("Something went wrong"),
-        }
+        };
     }
 
-    #[test]
-    fn action_test() {
-        let mut core = tokio_core::reactor::Core::new().unwrap();
-        let session = etcd_actions::EtcdSession::new(&core.handle(), "http://localhost:2379");
+    #[tokio::test]
+    async fn action_test() {
+        let etcd = etcd();
+        let session = etcd_actions::EtcdSession::new(&etcd.uri());
         let val = "booooom";
-        let work = session.put("action", val);
-        core.run(work).unwrap();
-        let work = session.get("action");
-        let result = core.run(work).unwrap();
+        session.put("action", val).await.unwrap();
+        let result = session.get("action").await.unwrap();
         assert_eq!(result, Some(String::from(val)));
-        let work = session.watch("pot");
-        let stream = core.run(work).unwrap(); // We have now registered a watch?
-        let new_put = session.put("pot", "boiled");
-        core.run(new_put).unwrap(); // We have now triggered the watch.
-        let work = stream.for_each(|inner| {
-            if let Some(_) = inner.created {
-                println!("Watch created");
+        let stream = session.watch("pot").await.unwrap(); // We have now registered a watch?
+        session.put("pot", "boiled").await.unwrap(); // We have now triggered the watch.
+        let mut stream = stream.try_filter(|inner| futures::future::ready(inner.created.is_none()));
+        let inner = stream.try_next().await.unwrap().unwrap();
+        println!("Event received");
+        let events = inner.events.as_ref().expect("Unexpected result");
+        assert!(events.len() == 1, "Should not have more than one event");
+        let ev = &events[0];
+        assert_eq!(ev.kv.as_ref().unwrap().key(), Some(String::from("pot")));
+        assert_eq!(
+            ev.kv.as_ref().unwrap().value(),
+            Some(String::from("boiled"))
+        );
+    }
+
+    #[tokio::test]
+    async fn watch_range_test() {
+        let etcd = etcd();
+        let session = etcd_actions::EtcdSession::new(&etcd.uri());
+        let stream = session.watch_pfx("kettle").await.unwrap(); // We have now registered a watch?
+        session.put("kettle-black", "boiled").await.unwrap(); // We have now triggered the watch.
+        let mut stream = stream.try_filter(|inner| futures::future::ready(inner.created.is_none()));
+        let inner = stream.try_next().await.unwrap().unwrap();
+        println!("Event received");
+        let events = inner.events.as_ref().expect("Unexpected result");
+        assert!(events.len() == 1, "Should not have more than one event");
+        let ev = &events[0];
+        assert_eq!(
+            ev.kv.as_ref().unwrap().key(),
+            Some(String::from("kettle-black"))
+        );
+        assert_eq!(
+            ev.kv.as_ref().unwrap().value(),
+            Some(String::from("boiled"))
+        );
+    }
+
+    #[tokio::test]
+    async fn get_range_test() {
+        let etcd = etcd();
+        let session = etcd_actions::EtcdSession::new(&etcd.uri());
+        for i in 0..7 {
+            session
+                .put(&format!("a:{}", i), &i.to_string())
+                .await
+                .unwrap();
+        }
+        let result = session.get_prefix("a:").await.unwrap();
+        assert_eq!(result.len(), 7);
+        for (k, v) in result {
+            println!("{} {}", k, v);
+        }
+    }
+
+    #[tokio::test]
+    async fn mutex_test() {
+        let etcd = etcd();
+        let session = etcd_actions::EtcdSession::new(&etcd.uri());
+        let mutex = etcd_lock::Mutex::new(&session, 10);
+        let guard = mutex.lock("mutex").await.unwrap();
+        assert!(guard.key().starts_with("mutex/"));
+        guard.unlock().await.unwrap();
+        // Since we unlocked above this should not block.
+        let guard = mutex.lock("mutex").await.unwrap();
+        guard.unlock().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn lock_test() {
+        let etcd = etcd();
+        let session = etcd_actions::EtcdSession::new(&etcd.uri());
+        let lock = etcd_lock::Lock::new(&session, "client-lock", 10);
+        let first = lock.lock().await.unwrap();
+        let token = first.fencing_token();
+        first.unlock().await.unwrap();
+        let second = lock.lock().await.unwrap();
+        assert!(second.fencing_token() > token);
+        second.unlock().await.unwrap();
+
+        // A watch that ends without a deletion must not be mistaken for one.
+        session.put("client-lock-held", "").await.unwrap();
+        let rev = session.get_prefix_raw("client-lock-held").await.unwrap();
+        let rev = rev.header.unwrap().rev();
+        let waiter = session.clone();
+        let waiting =
+            tokio::spawn(async move { waiter.wait_delete("client-lock-held", rev + 1).await });
+        while session.active_watches() == 0 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        session.cancel_watches();
+        assert!(waiting.await.unwrap().is_err());
+        session.delete("client-lock-held").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn election_test() {
+        let etcd = etcd();
+        let session = etcd_actions::EtcdSession::new(&etcd.uri());
+        let election = etcd_election::Election::new(&session, "election", 10);
+        let leadership = election.campaign("first").await.unwrap();
+        assert_eq!(
+            election.leader().await.unwrap(),
+            Some(String::from("first"))
+        );
+        let observed = election.observe().await.unwrap();
+        leadership.proclaim("second").await.unwrap();
+        let value = observed.skip(1).next().await.transpose().unwrap();
+        assert_eq!(value, Some(String::from("second")));
+        leadership.resign().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn session_test() {
+        let etcd = etcd();
+        let client = etcd_actions::EtcdSession::new(&etcd.uri());
+        let session = etcd_concurrency::Session::new(&client, 10).await.unwrap();
+        let mutex = etcd_lock::Mutex::with_session(&session);
+        let guard = mutex.lock("session-mutex").await.unwrap();
+        assert_eq!(guard.lease_id(), session.lease_id());
+        let election = etcd_election::Election::with_session(&session, "session-election");
+        let leadership = election.campaign("leader").await.unwrap();
+        assert_eq!(leadership.lease_id(), session.lease_id());
+        // Holders sharing the session lease still exclude each other.
+        let lock = etcd_lock::Lock::with_session(&session, "session-lock");
+        let first = lock.lock().await.unwrap();
+        let second = tokio::spawn({
+            let lock = etcd_lock::Lock::with_session(&session, "session-lock");
+            async move { lock.lock().await }
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!second.is_finished());
+        let token = first.fencing_token();
+        first.unlock().await.unwrap();
+        let second = second.await.unwrap().unwrap();
+        assert!(second.fencing_token() > token);
+        second.unlock().await.unwrap();
+        // Closing the session revokes the lease, releasing both the lock and the leadership.
+        let lock_key = String::from(guard.key());
+        session.close().await.unwrap();
+        assert_eq!(client.get(&lock_key).await.unwrap(), None);
+        assert!(election.leader().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn queue_test() {
+        let etcd = etcd();
+        let session = etcd_actions::EtcdSession::new(&etcd.uri());
+        let queue = etcd_queue::Queue::new(&session, "queue");
+        queue.enqueue("first").await.unwrap();
+        queue.enqueue("second").await.unwrap();
+        assert_eq!(queue.dequeue().await.unwrap(), "first");
+        assert_eq!(queue.dequeue().await.unwrap(), "second");
+        // The queue is now empty, so dequeue blocks until the next enqueue.
+        let (val, _) = futures::try_join!(queue.dequeue(), queue.enqueue("third")).unwrap();
+        assert_eq!(val, "third");
+    }
+
+    #[tokio::test]
+    async fn priority_queue_test() {
+        let etcd = etcd();
+        let session = etcd_actions::EtcdSession::new(&etcd.uri());
+        let queue = etcd_queue::PriorityQueue::new(&session, "priority-queue");
+        queue.enqueue("later", 5).await.unwrap();
+        queue.enqueue("urgent", 1).await.unwrap();
+        queue.enqueue("urgent too", 1).await.unwrap();
+        assert_eq!(queue.dequeue().await.unwrap(), "urgent");
+        assert_eq!(queue.dequeue().await.unwrap(), "urgent too");
+        assert_eq!(queue.dequeue().await.unwrap(), "later");
+
+        // A foreign key under a priority does not stop items from being queued behind it.
+        session.put("priority-queue/00002/zz", "foreign").await.unwrap();
+        queue.enqueue("normal", 2).await.unwrap();
+        queue.enqueue("normal too", 2).await.unwrap();
+        assert_eq!(queue.dequeue().await.unwrap(), "normal");
+        assert_eq!(queue.dequeue().await.unwrap(), "normal too");
+    }
+
+    #[tokio::test]
+    async fn barrier_test() {
+        let etcd = etcd();
+        let session = etcd_actions::EtcdSession::new(&etcd.uri());
+        let barrier = etcd_barrier::Barrier::new(&session, "barrier");
+        assert!(barrier.hold().await.unwrap());
+        match barrier.wait(Duration::from_millis(500)).await {
+            Err(etcd_error::Error::Timeout) => (),
+            _ => panic!("Wait should time out while the barrier is held"),
+        }
+        futures::try_join!(barrier.wait(Duration::from_secs(5)), barrier.release()).unwrap();
+
+        // A cancelled watch must not be mistaken for the barrier being released.
+        assert!(barrier.hold().await.unwrap());
+        let waiter = etcd_barrier::Barrier::new(&session, "barrier");
+        let waiting = tokio::spawn(async move { waiter.wait(Duration::from_secs(30)).await });
+        while session.active_watches() == 0 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        session.cancel_watches();
+        match waiting.await.unwrap() {
+            Err(etcd_error::Error::Timeout) | Ok(()) => panic!("Wait should fail with the watch"),
+            Err(_) => (),
+        }
+        barrier.release().await.unwrap();
+
+        let double = etcd_barrier::DoubleBarrier::new(&session, "double-barrier", 2, 10);
+        let (first, second) = futures::try_join!(
+            double.enter(Duration::from_secs(5)),
+            double.enter(Duration::from_secs(5))
+        )
+        .unwrap();
+        futures::try_join!(
+            first.leave(Duration::from_secs(5)),
+            second.leave(Duration::from_secs(5))
+        )
+        .unwrap();
+
+        // Only the ready key itself releases the participants, not keys that merely start with it.
+        let lonely = etcd_barrier::DoubleBarrier::new(&session, "double-barrier", 2, 10);
+        let entering = tokio::spawn(async move { lonely.enter(Duration::from_millis(500)).await });
+        while session.active_watches() == 0 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        session.put("double-barrier/ready-not", "").await.unwrap();
+        match entering.await.unwrap() {
+            Err(etcd_error::Error::Timeout) => (),
+            _ => panic!("Enter should time out with a single participant"),
+        }
+    }
+
+    #[tokio::test]
+    async fn rwlock_test() {
+        let etcd = etcd();
+        let session = etcd_actions::EtcdSession::new(&etcd.uri());
+        let lock = etcd_lock::RwLock::new(&session, "rwlock", 10);
+        // Readers do not exclude each other.
+        let first = lock.read().await.unwrap();
+        let second = lock.read().await.unwrap();
+        assert!(!first.is_exclusive());
+        futures::try_join!(first.unlock(), second.unlock()).unwrap();
+        let writer = lock.write().await.unwrap();
+        assert!(writer.is_exclusive());
+        writer.unlock().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn stm_test() {
+        let etcd = etcd();
+        let session = etcd_actions::EtcdSession::new(&etcd.uri());
+        session.put("stm-a", "10").await.unwrap();
+        session.put("stm-b", "0").await.unwrap();
+        let stm = etcd_stm::Stm::new(&session, etcd_stm::Isolation::Serializable);
+        let transfer = |ctx: etcd_stm::StmContext| async move {
+            let (a, b) = futures::try_join!(ctx.get("stm-a"), ctx.get("stm-b"))?;
+            let a = a.unwrap().parse::<i64>().unwrap();
+            let b = b.unwrap().parse::<i64>().unwrap();
+            ctx.put("stm-a", &(a - 5).to_string());
+            ctx.put("stm-b", &(b + 5).to_string());
+            Ok(())
+        };
+        // Both transfers conflict on the same keys, so one of them has to retry.
+        futures::try_join!(stm.run(transfer), stm.run(transfer)).unwrap();
+        assert_eq!(session.get("stm-a").await.unwrap(), Some(String::from("0")));
+        assert_eq!(
+            session.get("stm-b").await.unwrap(),
+            Some(String::from("10"))
+        );
+
+        // Deleting a key after it was read must make the attempt retry rather than overwrite.
+        let attempts = std::sync::atomic::AtomicUsize::new(0);
+        let stm = etcd_stm::Stm::new(&session, etcd_stm::Isolation::RepeatableReads);
+        stm.run(|ctx: etcd_stm::StmContext| {
+            let attempt = attempts.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            let session = session.clone();
+            async move {
+                let a = ctx.get("stm-a").await?;
+                if attempt == 0 {
+                    session.delete("stm-a").await?;
+                }
+                ctx.put("stm-b", a.as_deref().unwrap_or("gone"));
                 Ok(())
-            } else if let Some(ref events) = inner.events {
-                println!("Event received");
-                assert!(events.len() == 1, "Should not have more than one event");
-                let ev = &events[0];
-                assert_eq!(ev.kv.as_ref().unwrap().key(), Some(String::from("pot")));
-                assert_eq!(
-                    ev.kv.as_ref().unwrap().value(),
-                    Some(String::from("boiled"))
-                );
-                // Ugly hack to timeout the watch so we don't wait forever.
-                Err(hyper::Error::Io(
-                    io::Error::new(io::ErrorKind::TimedOut, "Done"),
-                ))
-            } else {
-                panic!("Unexpected result")
             }
-        });
-        match core.run(work) {
-            Ok(_) => panic!("Should not return OK"),
-            Err(hyper::Error::Io(err)) => {
-                assert!(
-                    err.kind() == io::ErrorKind::TimedOut,
-                    "IO error, but not a timeout"
-                )
+        })
+        .await
+        .unwrap();
+        assert_eq!(attempts.into_inner(), 2);
+        assert_eq!(
+            session.get("stm-b").await.unwrap(),
+            Some(String::from("gone"))
+        );
+    }
+
+    #[tokio::test]
+    async fn discovery_test() {
+        #[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+        struct Endpoint {
+            host: String,
+            port: u16,
+        }
+        let etcd = etcd();
+        let session = etcd_actions::EtcdSession::new(&etcd.uri());
+        let registry = etcd_discovery::ServiceRegistry::new(&session, 10);
+        let first = Endpoint {
+            host: String::from("10.0.0.1"),
+            port: 8080,
+        };
+        let registration = registry.register("web", "first", &first).await.unwrap();
+        let resolver = etcd_discovery::Resolver::<Endpoint>::new(&session, "web");
+        let mut stream = resolver.watch().await.unwrap();
+        match stream.try_next().await.unwrap() {
+            Some(etcd_discovery::ServiceEvent::Added(ref id, ref endpoint)) => {
+                assert_eq!(id, "first");
+                assert_eq!(endpoint, &first);
             }
-            _ => panic!("Something went wrong"),
+            _ => panic!("Expected the existing registration"),
+        }
+        assert_eq!(resolver.endpoints().len(), 1);
+        registration.deregister().await.unwrap();
+        match stream.try_next().await.unwrap() {
+            Some(etcd_discovery::ServiceEvent::Removed(ref id)) => assert_eq!(id, "first"),
+            _ => panic!("Expected the registration to be removed"),
         }
+        assert!(resolver.endpoints().is_empty());
     }
 
-    #[test]
-    fn watch_range_test() {
-        let mut core = tokio_core::reactor::Core::new().unwrap();
-        let session = etcd_actions::EtcdSession::new(&core.handle(), "http://localhost:2379");
-        let work = session.watch_pfx("kettle");
-        let stream = core.run(work).unwrap(); // We have now registered a watch?
-        let new_put = session.put("kettle-black", "boiled");
-        core.run(new_put).unwrap(); // We have now triggered the watch.
-        let work = stream.for_each(|inner| {
-            if let Some(_) = inner.created {
-                println!("Watch created");
-                Ok(())
-            } else if let Some(ref events) = inner.events {
-                println!("Event received");
-                assert!(events.len() == 1, "Should not have more than one event");
-                let ev = &events[0];
-                assert_eq!(
-                    ev.kv.as_ref().unwrap().key(),
-                    Some(String::from("kettle-black"))
-                );
-                assert_eq!(
-                    ev.kv.as_ref().unwrap().value(),
-                    Some(String::from("boiled"))
+    #[tokio::test]
+    async fn counter_test() {
+        let etcd = etcd();
+        let session = etcd_actions::EtcdSession::new(&etcd.uri());
+        session.delete("counter").await.unwrap();
+        let counter = etcd_counter::Counter::new(&session, "counter");
+        assert_eq!(counter.get().await.unwrap(), 0);
+        // Concurrent updates conflict, but every one of them is applied.
+        futures::try_join!(counter.increment(), counter.increment(), counter.add(5)).unwrap();
+        assert_eq!(counter.decrement().await.unwrap(), 6);
+        session.put("counter", "six").await.unwrap();
+        assert!(counter.get().await.is_err());
+        assert!(counter.increment().await.is_err());
+
+        session.delete("sequence").await.unwrap();
+        let block = std::num::NonZeroU32::new(2).unwrap();
+        let sequence = etcd_counter::Sequence::new(&session, "sequence", block);
+        let other = etcd_counter::Sequence::new(&session, "sequence", block);
+        assert_eq!(sequence.next().await.unwrap(), 1);
+        assert_eq!(other.next().await.unwrap(), 3);
+        assert_eq!(sequence.next().await.unwrap(), 2);
+        assert_eq!(sequence.next().await.unwrap(), 5);
+    }
+
+    #[tokio::test]
+    async fn semaphore_test() {
+        let etcd = etcd();
+        let session = etcd_actions::EtcdSession::new(&etcd.uri());
+        let semaphore = etcd_semaphore::Semaphore::new(&session, "semaphore", 2, 10);
+        let first = semaphore.acquire().await.unwrap();
+        let second = semaphore.acquire().await.unwrap();
+        // Both permits are taken, so the third holder has to wait for one to be released.
+        let (third, _) = futures::try_join!(semaphore.acquire(), first.release()).unwrap();
+        futures::try_join!(second.release(), third.release()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn namespace_test() {
+        let etcd = etcd();
+        let session = etcd_actions::EtcdSession::new(&etcd.uri());
+        let team = session.namespace("/team-a/");
+        team.put("config", "blue").await.unwrap();
+        assert_eq!(
+            session.get("/team-a/config").await.unwrap(),
+            Some(String::from("blue"))
+        );
+        assert_eq!(
+            team.get("config").await.unwrap(),
+            Some(String::from("blue"))
+        );
+        let result = team.get_prefix("conf").await.unwrap();
+        assert_eq!(result, vec![(String::from("config"), String::from("blue"))]);
+        let txn = TxnRequest::new(
+            vec![Compare::new_value("config", CompareResult::EQUAL, "blue")],
+            vec![RequestOp::Range(RangeRequest::new("config"))],
+            vec![],
+        );
+        let resp = team.txn(&txn).await.unwrap();
+        assert!(resp.succeeded());
+        match resp.responses.as_ref().unwrap()[0] {
+            ResponseOp::Range(ref range) => {
+                let kv = &range.kvs.as_ref().unwrap()[0];
+                assert_eq!(kv.key(), Some(String::from("config")));
+            }
+            _ => panic!("Expected a range response"),
+        }
+
+        // The empty prefix selects the whole namespace, and an empty namespace the whole store.
+        team.put("mode", "fast").await.unwrap();
+        session.put("/team-b/config", "red").await.unwrap();
+        let everything = |ns: &etcd_namespace::Namespace| {
+            let ns = ns.clone();
+            async move {
+                let txn = TxnRequest::new(
+                    vec![],
+                    vec![RequestOp::Range(RangeRequest::new_for_prefix(""))],
+                    vec![],
                 );
-                // Ugly hack to timeout the watch so we don't wait forever.
-                Err(hyper::Error::Io(
-                    io::Error::new(io::ErrorKind::TimedOut, "Done"),
-                ))
-            } else {
-                panic!("Unexpected result")
+                match ns.txn(&txn).await.unwrap().responses.unwrap().remove(0) {
+                    ResponseOp::Range(range) => range.count(),
+                    _ => panic!("Expected a range response"),
+                }
             }
+        };
+        assert_eq!(everything(&team).await, 2);
+        assert!(everything(&session.namespace("")).await >= 3);
+        assert_eq!(team.delete("mode").await.unwrap(), 1);
+        session.delete("/team-b/config").await.unwrap();
+        assert_eq!(team.delete("config").await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn scan_prefix_test() {
+        let etcd = etcd();
+        let session = etcd_actions::EtcdSession::new(&etcd.uri());
+        session.delete_prefix("scan:").await.unwrap();
+        for i in 0..7 {
+            session
+                .put(&format!("scan:{}", i), &i.to_string())
+                .await
+                .unwrap();
+        }
+        let kvs: Vec<KeyValue> = session.scan_prefix("scan:", 3).try_collect().await.unwrap();
+        assert_eq!(kvs.len(), 7);
+        for (i, kv) in kvs.iter().enumerate() {
+            assert_eq!(kv.key(), Some(format!("scan:{}", i)));
+        }
+
+        // The empty prefix scans the whole keyspace.
+        let kvs: Vec<KeyValue> = session.scan_prefix("", 2).try_collect().await.unwrap();
+        let keys: Vec<String> = kvs
+            .iter()
+            .filter_map(KeyValue::key)
+            .filter(|key| key.starts_with("scan:"))
+            .collect();
+        assert_eq!(keys.len(), 7);
+        assert!(kvs.windows(2).all(|w| w[0].key() < w[1].key()));
+    }
+
+    #[tokio::test]
+    async fn range_test() {
+        use etcd_range::RangeOptions;
+
+        let etcd = etcd();
+        let session = etcd_actions::EtcdSession::new(&etcd.uri());
+        session.delete_prefix("range:").await.unwrap();
+        for i in 0..5 {
+            session
+                .put(&format!("range:{}", i), &i.to_string())
+                .await
+                .unwrap();
+        }
+        let rev = session
+            .range(&RangeOptions::key("range:0"))
+            .await
+            .unwrap()
+            .rev();
+        session.put("range:0", "changed").await.unwrap();
+
+        let opts = RangeOptions::prefix("range:")
+            .sort(SortOrder::DESCEND, SortTarget::KEY)
+            .limit(2)
+            .keys_only();
+        let res = session.range(&opts).await.unwrap();
+        assert_eq!(res.count, 5);
+        assert!(res.more);
+        assert_eq!(res.kvs.len(), 2);
+        assert_eq!(res.kvs[0].key(), Some(String::from("range:4")));
+        assert_eq!(res.kvs[0].value(), None);
+
+        let res = session
+            .range(&RangeOptions::prefix("range:").count_only())
+            .await
+            .unwrap();
+        assert_eq!(res.count, 5);
+        assert!(res.kvs.is_empty());
+
+        let res = session
+            .range(&RangeOptions::interval("range:1", "range:3"))
+            .await
+            .unwrap();
+        assert_eq!(res.kvs.len(), 2);
+
+        let res = session
+            .range(&RangeOptions::key("range:0").revision(rev))
+            .await
+            .unwrap();
+        assert_eq!(res.kvs[0].value(), Some(String::from("0")));
+    }
+
+    #[tokio::test]
+    async fn typed_test() {
+        use etcd_codec::{Json, TypedEvent};
+
+        #[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+        struct Config {
+            name: String,
+            replicas: u32,
+        }
+
+        let etcd = etcd();
+        let session = etcd_actions::EtcdSession::new(&etcd.uri());
+        session.delete_prefix("typed:").await.unwrap();
+        let web = Config {
+            name: String::from("web"),
+            replicas: 3,
+        };
+        session.put_typed("typed:web", &web).await.unwrap();
+        assert_eq!(
+            session.get_typed::<Config>("typed:web").await.unwrap(),
+            Some(web.clone())
+        );
+        assert_eq!(
+            session.get_typed::<Config>("typed:none").await.unwrap(),
+            None
+        );
+
+        session.put("typed:bad", "not json").await.unwrap();
+        assert!(session.get_typed::<Config>("typed:bad").await.is_err());
+        session.delete("typed:bad").await.unwrap();
+
+        let configs = session.get_prefix_typed::<Config>("typed:").await.unwrap();
+        assert_eq!(configs, vec![(String::from("typed:web"), web.clone())]);
+
+        let typed = session.typed::<Config, _>(Json);
+        let watch = typed.watch_pfx("typed:").await.unwrap();
+        typed.put("typed:db", &web).await.unwrap();
+        session.delete("typed:db").await.unwrap();
+        let events: Vec<TypedEvent<Config>> = watch.take(2).try_collect().await.unwrap();
+        match events[0] {
+            TypedEvent::Put(ref key, ref val) => {
+                assert_eq!(key, "typed:db");
+                assert_eq!(val, &web);
+            }
+            TypedEvent::Delete(_) => panic!("expected a put"),
+        }
+        match events[1] {
+            TypedEvent::Delete(ref key) => assert_eq!(key, "typed:db"),
+            TypedEvent::Put(..) => panic!("expected a delete"),
+        }
+
+        // Values only need to serialise to be put, so borrowed data works too.
+        session
+            .put_typed("typed:name", &web.name.as_str())
+            .await
+            .unwrap();
+        assert_eq!(
+            session.get("typed:name").await.unwrap(),
+            Some(String::from("\"web\""))
+        );
+        session.delete("typed:name").await.unwrap();
+
+        // Reading only needs the values to deserialise.
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Replicas {
+            replicas: u32,
+        }
+        assert_eq!(
+            session.get_typed::<Replicas>("typed:web").await.unwrap(),
+            Some(Replicas { replicas: 3 })
+        );
+        let replicas = session.get_prefix_typed::<Replicas>("typed:").await.unwrap();
+        assert_eq!(replicas.len(), 1);
+        let mut watch = session.watch_pfx_typed::<Replicas>("typed:").await.unwrap();
+        typed.put("typed:db", &web).await.unwrap();
+        match watch.try_next().await.unwrap() {
+            Some(TypedEvent::Put(key, val)) => {
+                assert_eq!(key, "typed:db");
+                assert_eq!(val, Replicas { replicas: 3 });
+            }
+            _ => panic!("expected a put"),
+        }
+        session.delete("typed:db").await.unwrap();
+
+        // The untyped readers decode binary values lossily instead of panicking.
+        #[cfg(feature = "bincode")]
+        {
+            let big = Config {
+                replicas: u32::MAX,
+                ..web.clone()
+            };
+            let binary = session.typed::<Config, _>(etcd_codec::Bincode);
+            binary.put("typed:bin", &big).await.unwrap();
+            let resp = session
+                .range_raw(&RangeRequest::new("typed:bin"))
+                .await
+                .unwrap();
+            let raw = resp.kvs.unwrap()[0].value_as_u8().unwrap();
+            assert!(str::from_utf8(&raw).is_err());
+            let text = session.get("typed:bin").await.unwrap().unwrap();
+            assert_eq!(text, String::from_utf8_lossy(&raw));
+            assert_eq!(session.get_prefix("typed:bin").await.unwrap().len(), 1);
+            assert_eq!(binary.get("typed:bin").await.unwrap(), Some(big));
+            session.delete("typed:bin").await.unwrap();
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn shared_session_test() {
+        fn assert_shareable<T: Clone + Send + Sync + 'static>() {}
+        assert_shareable::<etcd_actions::EtcdSession>();
+
+        let etcd = etcd();
+        let session = etcd_actions::EtcdSession::new(&etcd.uri());
+        session.delete_prefix("shared:").await.unwrap();
+        let mut watch = session.watch_pfx("shared:").await.unwrap();
+        assert_eq!(session.active_watches(), 1);
+
+        let tasks: Vec<_> = (0..8)
+            .map(|i| {
+                let session = session.clone();
+                tokio::spawn(async move { session.put(&format!("shared:{}", i), "x").await })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap().unwrap();
+        }
+        assert_eq!(session.get_prefix("shared:").await.unwrap().len(), 8);
+
+        session.cancel_watches();
+        while watch.try_next().await.unwrap().is_some() {}
+        drop(watch);
+        assert_eq!(session.active_watches(), 0);
+        session.delete_prefix("shared:").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn memory_test() {
+        use etcd_kv::{Kv, Lease, Watch};
+
+        let store = etcd_memory::MemoryStore::new();
+        let mut watch = store.watch_pfx("mem:").await.unwrap();
+        assert!(watch.try_next().await.unwrap().unwrap().created.unwrap());
+
+        store.put("mem:a", "1").await.unwrap();
+        store.put("mem:a", "2").await.unwrap();
+        store.put("mem:b", "3").await.unwrap();
+        assert_eq!(store.revision(), 4);
+        assert_eq!(store.get("mem:a").await.unwrap(), Some(String::from("2")));
+
+        let result = store
+            .range(&etcd_range::RangeOptions::prefix("mem:").revision(2))
+            .await
+            .unwrap();
+        assert_eq!(result.kvs.len(), 1);
+        assert_eq!(result.kvs[0].value(), Some(String::from("1")));
+        let result = store
+            .range(&etcd_range::RangeOptions::prefix("mem:").limit(1))
+            .await
+            .unwrap();
+        assert_eq!((result.count, result.more, result.rev()), (2, true, 4));
+        assert_eq!(result.kvs[0].create_rev(), 2);
+        assert_eq!(result.kvs[0].mod_rev(), 3);
+
+        let txn = TxnRequest::new(
+            vec![Compare::new_create_revision("mem:c", CompareResult::EQUAL, 0)],
+            vec![
+                RequestOp::Put(PutRequest::new("mem:c", "4")),
+                RequestOp::DeleteRange(DeleteRangeRequest::new("mem:b")),
+            ],
+            vec![],
+        );
+        let resp = store.txn(&txn).await.unwrap();
+        assert!(resp.succeeded());
+        assert_eq!(resp.header.unwrap().rev(), 5);
+        assert!(!store.txn(&txn).await.unwrap().succeeded());
+
+        for expected in [2, 3, 4] {
+            let resp = watch.try_next().await.unwrap().unwrap();
+            assert_eq!(resp.header.unwrap().rev(), expected);
+        }
+        let resp = watch.try_next().await.unwrap().unwrap();
+        assert_eq!(resp.events.unwrap().len(), 2);
+
+        let replay = WatchCreateRequest {
+            start_revision: Some(String::from("3")),
+            ..WatchCreateRequest::new_for_key("mem:a")
+        };
+        let mut replay = store.watch_with(replay).await.unwrap();
+        replay.try_next().await.unwrap();
+        let events = replay.try_next().await.unwrap().unwrap().events.unwrap();
+        assert_eq!(events[0].kv.as_ref().unwrap().value(), Some(String::from("2")));
+
+        let lease = store.lease_grant(10).await.unwrap().id().unwrap();
+        store.put_with_lease("mem:d", "5", lease).await.unwrap();
+        store.advance(Duration::from_secs(8));
+        assert_eq!(store.lease_keep_alive(lease).await.unwrap().ttl(), 10);
+        store.advance(Duration::from_secs(8));
+        assert_eq!(store.get("mem:d").await.unwrap(), Some(String::from("5")));
+        store.advance(Duration::from_secs(3));
+        assert_eq!(store.get("mem:d").await.unwrap(), None);
+        assert_eq!(store.lease_keep_alive(lease).await.unwrap().ttl(), 0);
+        assert!(store.put_with_lease("mem:e", "6", lease).await.is_err());
+
+        // A transaction failing partway leaves the store as it was.
+        let rev = store.revision();
+        let lease = store.lease_grant(10).await.unwrap().id().unwrap();
+        let failing = TxnRequest::new(
+            vec![],
+            vec![
+                RequestOp::Put(PutRequest::new_with_lease("mem:a", "7", lease)),
+                RequestOp::DeleteRange(DeleteRangeRequest::new("mem:c")),
+                RequestOp::Put(PutRequest::new("mem:f", "8")),
+                RequestOp::Range(RangeRequest {
+                    revision: Some((rev + 10).to_string()),
+                    ..RangeRequest::new("mem:a")
+                }),
+            ],
+            vec![],
+        );
+        assert!(store.txn(&failing).await.is_err());
+        assert_eq!(store.revision(), rev);
+        assert_eq!(store.get("mem:a").await.unwrap(), Some(String::from("2")));
+        assert_eq!(store.get("mem:c").await.unwrap(), Some(String::from("4")));
+        assert_eq!(store.get("mem:f").await.unwrap(), None);
+        store.lease_revoke(lease).await.unwrap();
+        assert_eq!(store.get("mem:a").await.unwrap(), Some(String::from("2")));
+        store.put("mem:f", "9").await.unwrap();
+        assert_eq!(store.revision(), rev + 1);
+        assert_eq!(store.delete_prefix("mem:").await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn cluster_test() {
+        let etcd = etcd();
+        let session = etcd_actions::EtcdSession::new(&etcd.uri());
+        let members = session.member_list().await.unwrap().members.unwrap();
+        assert!(!members.is_empty());
+        let status = session.status().await.unwrap();
+        assert!(members.iter().any(|m| m.id() == status.member_id()));
+        assert!(status.version.is_some());
+        assert!(status.raft_term() > 0);
+
+        session.put("cluster:a", "1").await.unwrap();
+        let resp = session
+            .put_raw(&PutRequest {
+                prev_kv: Some(true),
+                ..PutRequest::new("cluster:a", "2")
+            })
+            .await
+            .unwrap();
+        assert_eq!(resp.prev_kv.unwrap().value().unwrap(), "1");
+        let resp = session
+            .delete_range_raw(&DeleteRangeRequest::new("cluster:a"))
+            .await
+            .unwrap();
+        assert_eq!(resp.deleted(), 1);
+    }
+
+    #[tokio::test]
+    async fn export_test() {
+        use etcd_export::{Encoding, Export, Import, ImportMode};
+
+        let etcd = etcd();
+        let session = etcd_actions::EtcdSession::new(&etcd.uri());
+        session.delete_prefix("export:").await.unwrap();
+        session.delete_prefix("imported:").await.unwrap();
+        for i in 0..5 {
+            session.put(&format!("export:{}", i), "v").await.unwrap();
+        }
+        session.put_bytes("export:bin", &[0xff, 0]).await.unwrap();
+        let rev = session.get_prefix_raw("export:").await.unwrap().header.unwrap().rev();
+        // Neither change is part of an export pinned before it.
+        session.put("export:0", "changed").await.unwrap();
+        session.put("export:new", "v").await.unwrap();
+
+        let mut dump = Vec::new();
+        let summary = Export::new(&session, "export:")
+            .page_size(2)
+            .revision(rev)
+            .write_to(&mut dump)
+            .await
+            .unwrap();
+        assert_eq!(summary.revision, rev);
+        assert_eq!(summary.keys, 6);
+        let records: Vec<_> = etcd_export::read_records(&dump[..])
+            .collect::<etcd_error::Result<_>>()
+            .unwrap();
+        assert_eq!(records[0].key, "export:0");
+        assert_eq!(records[0].value, "v");
+        assert_eq!(records[0].encoding, Encoding::Utf8);
+        assert!(records[0].mod_revision <= rev);
+        assert_eq!(records[5].encoding, Encoding::Base64);
+        assert_eq!(records[5].value_bytes().unwrap(), vec![0xff, 0]);
+
+        session.put("imported:3", "kept").await.unwrap();
+        let import = Import::new(&session)
+            .rewrite_prefix("export:", "imported:")
+            .mode(ImportMode::PutIfAbsent)
+            .batch_size(4);
+        let summary = import.dry_run(true).read_from(&dump[..]).await.unwrap();
+        assert_eq!((summary.imported, summary.skipped, summary.batches), (5, 1, 2));
+        assert_eq!(session.get_prefix_raw("imported:").await.unwrap().count(), 1);
+
+        let import = Import::new(&session)
+            .rewrite_prefix("export:", "imported:")
+            .mode(ImportMode::PutIfAbsent)
+            .batch_size(4);
+        let summary = import.read_from(&dump[..]).await.unwrap();
+        assert_eq!((summary.imported, summary.skipped), (5, 1));
+        assert_eq!(session.get("imported:0").await.unwrap().unwrap(), "v");
+        assert_eq!(session.get("imported:3").await.unwrap().unwrap(), "kept");
+        assert_eq!(session.get_prefix_raw("imported:").await.unwrap().count(), 6);
+
+        let summary = Import::new(&session)
+            .rewrite_prefix("export:", "imported:")
+            .read_from(&dump[..])
+            .await
+            .unwrap();
+        assert_eq!(summary.imported, 6);
+        assert_eq!(session.get("imported:3").await.unwrap().unwrap(), "v");
+        assert!(Import::new(&session)
+            .rewrite_prefix("other:", "imported:")
+            .read_from(&dump[..])
+            .await
+            .is_err());
+        session.delete_prefix("export:").await.unwrap();
+        session.delete_prefix("imported:").await.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn mirror_test() {
+        use etcd_mirror::Mirror;
+
+        /// Wait until the destination prefix holds exactly `expected`.
+        async fn synced(session: &etcd_actions::EtcdSession, expected: &[(&str, &str)]) {
+            for _ in 0..200 {
+                let kvs = session.get_prefix("mirror-dst:").await.unwrap();
+                let kvs: Vec<_> = kvs.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+                if kvs == expected {
+                    return;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            panic!("destination never caught up with {:?}", expected);
+        }
+
+        let etcd = etcd();
+        let destination = etcd_actions::EtcdSession::new(&etcd.uri());
+        destination.delete_prefix("mirror-").await.unwrap();
+        let source = etcd_actions::EtcdSession::new(&etcd.uri());
+        source.put("mirror-src:a", "1").await.unwrap();
+        source.put("mirror-src:b", "2").await.unwrap();
+
+        let mirror = Mirror::new(&source, &destination, "mirror-src:")
+            .rewrite_prefix("mirror-dst:")
+            .checkpoint_key("mirror-checkpoint")
+            .page_size(1);
+        let metrics = mirror.metrics();
+        let running = tokio::spawn(async move { mirror.run().await });
+        synced(&destination, &[("mirror-dst:a", "1"), ("mirror-dst:b", "2")]).await;
+        assert_eq!(metrics.keys_copied(), 2);
+
+        source.put("mirror-src:c", "3").await.unwrap();
+        source.delete("mirror-src:a").await.unwrap();
+        synced(&destination, &[("mirror-dst:b", "2"), ("mirror-dst:c", "3")]).await;
+        source.cancel_watches();
+        running.await.unwrap().unwrap();
+        assert_eq!(metrics.events_applied(), 2);
+        let checkpoint = destination.get("mirror-checkpoint").await.unwrap().unwrap();
+        assert_eq!(checkpoint, metrics.applied_revision().to_string());
+        assert!(metrics.source_revision() >= metrics.applied_revision());
+
+        // A restarted mirror picks up from the checkpoint rather than copying again.
+        source.put("mirror-src:d", "4").await.unwrap();
+        let source = etcd_actions::EtcdSession::new(&etcd.uri());
+        let mirror = Mirror::new(&source, &destination, "mirror-src:")
+            .rewrite_prefix("mirror-dst:")
+            .checkpoint_key("mirror-checkpoint");
+        let metrics = mirror.metrics();
+        let running = tokio::spawn(async move { mirror.run().await });
+        synced(
+            &destination,
+            &[("mirror-dst:b", "2"), ("mirror-dst:c", "3"), ("mirror-dst:d", "4")],
+        )
+        .await;
+        source.cancel_watches();
+        running.await.unwrap().unwrap();
+        assert_eq!(metrics.keys_copied(), 0);
+        assert_eq!(metrics.events_applied(), 1);
+        assert_eq!(metrics.lag(), 0);
+        destination.delete_prefix("mirror-").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn replay_test() {
+        use etcd_transport::{Cassette, Interaction, Replayer};
+
+        let put = Interaction {
+            endpoint: String::from("/v3alpha/kv/put"),
+            request: serde_json::to_value(PutRequest::new("replay:a", "1")).unwrap(),
+            status: 200,
+            chunks: vec![String::from(r#"{"header":{"revision":"2"}}"#)],
+            complete: true,
+        };
+        let replayer = Replayer::new(Cassette {
+            interactions: vec![put],
         });
-        match core.run(work) {
-            Ok(_) => panic!("Should not return OK"),
-            Err(hyper::Error::Io(err)) => {
-                assert!(
-                    err.kind() == io::ErrorKind::TimedOut,
-                    "IO error, but not a timeout"
-                )
+        let session =
+            etcd_actions::EtcdSession::with_transport("http://replay.invalid", replayer.clone());
+        assert!(session.put("replay:a", "1").await.unwrap());
+        assert_eq!(replayer.remaining(), 0);
+
+        // Each recording is served once, anything else is flagged.
+        match session.put("replay:a", "1").await {
+            Err(etcd_error::Error::Unrecorded(_)) => {}
+            other => panic!("expected an unrecorded request, got {:?}", other),
+        }
+        assert!(session.get("replay:b").await.is_err());
+        let unexpected = replayer.unexpected();
+        assert_eq!(unexpected.len(), 2);
+        assert!(unexpected[1].starts_with("/v3alpha/kv/range "));
+
+        // An error frame from the gateway ends up as an error, not a panic.
+        let error = r#"{"error":{"grpc_code":14,"message":"etcdserver: no leader"}}"#;
+        let watch = Interaction {
+            endpoint: String::from("/v3alpha/watch"),
+            request: serde_json::to_value(WatchRequest::new_create_request(
+                WatchCreateRequest::new_for_key("replay:w"),
+            ))
+            .unwrap(),
+            status: 200,
+            chunks: vec![
+                String::from("{\"result\":{\"header\":{\"revision\":\"2\"},\"created\":true}}\n"),
+                format!("{}\n", error),
+            ],
+            complete: true,
+        };
+        let keep_alive = Interaction {
+            endpoint: String::from("/v3alpha/lease/keepalive"),
+            request: serde_json::to_value(LeaseKeepAliveRequest::new(7)).unwrap(),
+            status: 200,
+            chunks: vec![String::from(error)],
+            complete: true,
+        };
+        let replayer = Replayer::new(Cassette {
+            interactions: vec![watch, keep_alive],
+        });
+        let session =
+            etcd_actions::EtcdSession::with_transport("http://replay.invalid", replayer.clone());
+        let mut watch = session.watch("replay:w").await.unwrap();
+        assert!(watch.try_next().await.unwrap().unwrap().created.unwrap());
+        match watch.try_next().await {
+            Err(err) => assert!(err.to_string().contains("no leader")),
+            Ok(_) => panic!("expected the error frame to fail the watch"),
+        }
+        match session.lease_keep_alive(7).await {
+            Err(err) => assert!(err.to_string().contains("no leader")),
+            Ok(_) => panic!("expected the error frame to fail the keep alive"),
+        }
+        // So does a lease grant that reports an error instead of an ID.
+        let grant = Interaction {
+            endpoint: String::from("/v3alpha/lease/grant"),
+            request: serde_json::to_value(LeaseGrantRequest::new(10)).unwrap(),
+            status: 200,
+            chunks: vec![String::from(r#"{"error":"etcdserver: too many leases"}"#)],
+            complete: true,
+        };
+        let replayer = Replayer::new(Cassette {
+            interactions: vec![grant],
+        });
+        let session =
+            etcd_actions::EtcdSession::with_transport("http://replay.invalid", replayer.clone());
+        match etcd_concurrency::Session::new(&session, 10).await {
+            Err(err) => assert!(err.to_string().contains("too many leases")),
+            Ok(_) => panic!("expected the failed grant to fail the session"),
+        }
+    }
+
+    #[cfg(feature = "test-server")]
+    #[tokio::test]
+    async fn record_test() {
+        use etcd_transport::{HttpTransport, Recorder, Replayer};
+
+        async fn exercise(session: &etcd_actions::EtcdSession) -> Vec<Event> {
+            let mut watch = session.watch_pfx("record:").await.unwrap();
+            assert!(watch.try_next().await.unwrap().unwrap().created.unwrap());
+            session.put("record:a", "1").await.unwrap();
+            session.put("record:b", "2").await.unwrap();
+            assert_eq!(session.get("record:a").await.unwrap().unwrap(), "1");
+            let mut events = Vec::new();
+            while events.len() < 2 {
+                let resp = watch.try_next().await.unwrap().unwrap();
+                events.extend(resp.events.unwrap_or_default());
             }
-            _ => panic!("Something went wrong"),
+            events
         }
+
+        let etcd = etcd();
+        let recorder = Recorder::new(HttpTransport::new());
+        let recording = recorder.recording();
+        let session = etcd_actions::EtcdSession::with_transport(&etcd.uri(), recorder);
+        let recorded = exercise(&session).await;
+        drop(etcd);
+
+        let path = std::env::temp_dir().join(format!("etcdv3rs-{}.json", std::process::id()));
+        recording.save(&path).unwrap();
+        let replayer = Replayer::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(replayer.remaining(), 4);
+
+        let session =
+            etcd_actions::EtcdSession::with_transport("http://replay.invalid", replayer.clone());
+        let replayed = exercise(&session).await;
+        let values = |events: &[Event]| -> Vec<_> {
+            events
+                .iter()
+                .map(|ev| ev.kv.as_ref().unwrap().value())
+                .collect()
+        };
+        assert_eq!(values(&replayed), values(&recorded));
+        assert_eq!(replayer.remaining(), 0);
+        assert!(replayer.unexpected().is_empty());
     }
 
+    #[cfg(feature = "blocking")]
     #[test]
-    fn get_range_test() {
-        let mut core = tokio_core::reactor::Core::new().unwrap();
-        let session = etcd_actions::EtcdSession::new(&core.handle(), "http://localhost:2379");
-        core.run(session.put("a:0", "0")).unwrap();
-        core.run(session.put("a:1", "1")).unwrap();
-        core.run(session.put("a:2", "2")).unwrap();
-        core.run(session.put("a:3", "3")).unwrap();
-        core.run(session.put("a:4", "4")).unwrap();
-        core.run(session.put("a:5", "5")).unwrap();
-        core.run(session.put("a:6", "6")).unwrap();
-        let result = core.run(session.get_prefix("a:")).unwrap();
-        assert_eq!(result.len(), 7);
-        for (k, v) in result {
-            println!("{} {}", k, v);
-        }
+    fn blocking_test() {
+        let etcd = etcd();
+        let client = blocking::Client::new(&etcd.uri()).unwrap();
+        client.delete_prefix("blocking:").unwrap();
+        let watcher = client.watch_pfx("blocking:").unwrap();
+        assert!(client.put("blocking:a", "1").unwrap());
+        assert_eq!(client.get("blocking:a").unwrap(), Some(String::from("1")));
+        assert_eq!(
+            client.get_prefix("blocking:").unwrap(),
+            vec![(String::from("blocking:a"), String::from("1"))]
+        );
+
+        let lease = client.lease_grant(10).unwrap().id().unwrap();
+        client.put_with_lease("blocking:b", "2", lease).unwrap();
+        client.lease_revoke(lease).unwrap();
+        assert_eq!(client.get("blocking:b").unwrap(), None);
+        assert_eq!(client.delete("blocking:a").unwrap(), 1);
+
+        let events = watcher.take(4).collect::<Result<Vec<_>, _>>().unwrap();
+        let deletes = events
+            .iter()
+            .filter(|ev| ev.event_type() == &Some(EventType::DELETE))
+            .count();
+        assert_eq!(deletes, 2);
     }
 }
@@ -0,0 +1,368 @@
+//! A fake `etcd` gRPC gateway for integration tests. `TestServer` answers the same JSON endpoints
+//! as the real gateway on an ephemeral loopback port, backed by a `MemoryStore`, so code using
+//! an `EtcdSession` can be tested without a running `etcd`. The `v3lock` and `v3election`
+//! services are implemented with the same key layout `etcd` uses for them.
+
+use super::etcd_actions::*;
+use super::etcd_error::{Error, Result};
+use super::etcd_kv::{Kv, Lease, Watch};
+use super::etcd_lock::{claim_key, claim_key_with_value, wait_deletes};
+use super::etcd_memory::{self, MemoryStore};
+use super::etcd_proto::*;
+use bytes::Bytes;
+use futures::channel::oneshot;
+use futures::future;
+use futures::stream::{self, BoxStream};
+use futures::{StreamExt, TryStreamExt};
+use http_body_util::combinators::UnsyncBoxBody;
+use http_body_util::{BodyExt, Full, StreamBody};
+use hyper::body::{Frame, Incoming};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::convert::Infallible;
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+use std::thread;
+
+type Body = UnsyncBoxBody<Bytes, Infallible>;
+
+/// A running fake gateway. The server runs on its own thread, so it can be used from any runtime
+/// or none at all, and shuts down when this is dropped.
+pub struct TestServer {
+    addr: SocketAddr,
+    store: MemoryStore,
+    _shutdown: oneshot::Sender<()>,
+}
+
+impl TestServer {
+    /// Start a server with an empty store.
+    pub fn start() -> io::Result<TestServer> {
+        TestServer::with_store(MemoryStore::new())
+    }
+
+    /// Start a server answering from `store`.
+    pub fn with_store(store: MemoryStore) -> io::Result<TestServer> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        listener.set_nonblocking(true)?;
+        let addr = listener.local_addr()?;
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let (shutdown, stopped) = oneshot::channel::<()>();
+        let served = store.clone();
+        thread::spawn(move || {
+            runtime.block_on(async move {
+                let listener = match tokio::net::TcpListener::from_std(listener) {
+                    Ok(listener) => listener,
+                    Err(_) => return,
+                };
+                let accept = async move {
+                    loop {
+                        let stream = match listener.accept().await {
+                            Ok((stream, _)) => stream,
+                            Err(_) => continue,
+                        };
+                        let store = served.clone();
+                        let service = service_fn(move |req| serve(store.clone(), req));
+                        tokio::spawn(
+                            http1::Builder::new().serve_connection(TokioIo::new(stream), service),
+                        );
+                    }
+                };
+                future::select(Box::pin(accept), stopped).await;
+            })
+        });
+        Ok(TestServer {
+            addr,
+            store,
+            _shutdown: shutdown,
+        })
+    }
+
+    /// Base URI to create an `EtcdSession` with.
+    pub fn uri(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// The store being served, e.g., to advance its clock and expire leases.
+    pub fn store(&self) -> &MemoryStore {
+        &self.store
+    }
+}
+
+async fn serve(
+    store: MemoryStore,
+    req: Request<Incoming>,
+) -> ::std::result::Result<Response<Body>, Infallible> {
+    let path = String::from(req.uri().path());
+    let host = req
+        .headers()
+        .get(hyper::header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .map(|host| format!("http://{}", host))
+        .unwrap_or_default();
+    let resp = match req.into_body().collect().await {
+        Ok(body) => route(&store, &path, &host, &body.to_bytes()).await,
+        Err(err) => Err(Error::from(err)),
+    };
+    Ok(resp.unwrap_or_else(|err| {
+        let status = match err {
+            Error::Status(status) => status,
+            Error::Json(_) => StatusCode::BAD_REQUEST,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        let mut resp = Response::new(full(format!(
+            r#"{{"error":"{}","code":{}}}"#,
+            status,
+            status.as_u16()
+        )));
+        *resp.status_mut() = status;
+        resp
+    }))
+}
+
+async fn route(store: &MemoryStore, path: &str, host: &str, body: &[u8]) -> Result<Response<Body>> {
+    match path {
+        PUT_ENDPOINT => unary(&store.put_raw(&parse(body)?)?),
+        RANGE_ENDPOINT => unary(&store.range_raw(&parse(body)?)?),
+        DELETE_RANGE_ENDPOINT => unary(&store.delete_range_raw(&parse(body)?)?),
+        TXN_ENDPOINT => unary(&store.txn(&parse(body)?).await?),
+        WATCH_ENDPOINT => match parse(body)? {
+            WatchRequest::CreateRequest(create_request) => {
+                let watch = store.watch_with(create_request).await?;
+                Ok(streaming(watch.map_ok(|resp| WatchStreamResponse {
+                    result: Some(resp),
+                    error: None,
+                })))
+            }
+            // Watches are cancelled by closing their connection.
+            WatchRequest::CancelRequest(_) => Err(Error::Status(StatusCode::BAD_REQUEST)),
+        },
+        LEASE_GRANT_ENDPOINT => {
+            let req: LeaseGrantRequest = parse(body)?;
+            unary(&store.lease_grant(number(&req.ttl)).await?)
+        }
+        LEASE_REVOKE_ENDPOINT => {
+            let req: LeaseRevokeRequest = parse(body)?;
+            store.lease_revoke(number(&req.id)).await?;
+            unary(&LeaseRevokeResponse {
+                header: Some(header(store)),
+            })
+        }
+        LEASE_KEEPALIVE_ENDPOINT => {
+            let req: LeaseKeepAliveRequest = parse(body)?;
+            let resp = store.lease_keep_alive(number(&req.id)).await?;
+            unary(&LeaseKeepAliveStreamResponse {
+                result: Some(resp),
+                error: None,
+            })
+        }
+        LOCK_ENDPOINT => unary(&lock(store, parse(body)?).await?),
+        UNLOCK_ENDPOINT => {
+            let req: UnlockRequest = parse(body)?;
+            store.delete(&text(&req.key)).await?;
+            unary(&UnlockResponse {
+                header: Some(header(store)),
+            })
+        }
+        CAMPAIGN_ENDPOINT => unary(&campaign(store, parse(body)?).await?),
+        PROCLAIM_ENDPOINT => unary(&proclaim(store, parse(body)?).await?),
+        LEADER_ENDPOINT => {
+            let req: LeaderRequest = parse(body)?;
+            let resp = leader(store, &text(&req.name))?;
+            match resp.kv {
+                Some(_) => unary(&resp),
+                None => Err(Error::Status(StatusCode::NOT_FOUND)),
+            }
+        }
+        OBSERVE_ENDPOINT => {
+            let req: LeaderRequest = parse(body)?;
+            let observed = observe(store.clone(), text(&req.name)).await?;
+            Ok(streaming(observed.map_ok(|resp| LeaderStreamResponse {
+                result: Some(resp),
+                error: None,
+            })))
+        }
+        RESIGN_ENDPOINT => unary(&resign(store, parse(body)?).await?),
+        AUTHENTICATE_ENDPOINT => unary(&AuthenticateResponse {
+            header: Some(header(store)),
+            token: Some(String::from("test-token")),
+        }),
+        // A single member, which is also the leader, with the ID `etcd_memory::header` reports.
+        MEMBER_LIST_ENDPOINT => unary(&MemberListResponse {
+            header: Some(header(store)),
+            members: Some(vec![Member::new(1, "test-server", host)]),
+        }),
+        STATUS_ENDPOINT => unary(&StatusResponse {
+            header: Some(header(store)),
+            version: Some(String::from("test-server")),
+            leader: Some(String::from("1")),
+            raft_index: Some(store.revision().to_string()),
+            raft_term: Some(String::from("1")),
+            ..Default::default()
+        }),
+        _ => Err(Error::Status(StatusCode::NOT_FOUND)),
+    }
+}
+
+/// Acquire a lock the way the `v3lock` service does: create `name/<lease>` and wait for every
+/// key under `name/` created before it to be deleted.
+async fn lock(store: &MemoryStore, req: LockRequest) -> Result<LockResponse> {
+    let name = text(&req.name);
+    let key = format!("{}/{:x}", name, number(&req.lease));
+    let rev = claim_key(store, &key, number(&req.lease)).await?;
+    wait_deletes(store, &format!("{}/", name), rev - 1).await?;
+    let mut resp = LockResponse::new(&key);
+    resp.header = Some(header(store));
+    Ok(resp)
+}
+
+/// Campaign the way the `v3election` service does, which uses the same layout as `lock` with the
+/// value stored in the campaign key.
+async fn campaign(store: &MemoryStore, req: CampaignRequest) -> Result<CampaignResponse> {
+    let name = text(&req.name);
+    let lease = number(&req.lease);
+    let key = format!("{}/{:x}", name, lease);
+    let rev = claim_key_with_value(store, &key, &text(&req.value), lease).await?;
+    wait_deletes(store, &format!("{}/", name), rev - 1).await?;
+    Ok(CampaignResponse {
+        header: Some(header(store)),
+        leader: Some(LeaderKey {
+            name: req.name,
+            key: Some(base64::encode(&key)),
+            rev: Some(rev.to_string()),
+            lease: req.lease,
+        }),
+    })
+}
+
+async fn proclaim(store: &MemoryStore, req: ProclaimRequest) -> Result<ProclaimResponse> {
+    let leader = req.leader.unwrap_or_default();
+    let key = leader.key().unwrap_or_default();
+    let txn = TxnRequest::new(
+        vec![Compare::new_create_revision(
+            &key,
+            CompareResult::EQUAL,
+            leader.rev(),
+        )],
+        vec![RequestOp::Put(PutRequest::new_with_lease(
+            &key,
+            &text(&req.value),
+            number(&leader.lease),
+        ))],
+        vec![],
+    );
+    if !store.txn(&txn).await?.succeeded() {
+        return Err(Error::Status(StatusCode::BAD_REQUEST));
+    }
+    Ok(ProclaimResponse {
+        header: Some(header(store)),
+    })
+}
+
+async fn resign(store: &MemoryStore, req: ResignRequest) -> Result<ResignResponse> {
+    let leader = req.leader.unwrap_or_default();
+    let key = leader.key().unwrap_or_default();
+    let txn = TxnRequest::new(
+        vec![Compare::new_create_revision(
+            &key,
+            CompareResult::EQUAL,
+            leader.rev(),
+        )],
+        vec![RequestOp::DeleteRange(DeleteRangeRequest::new(&key))],
+        vec![],
+    );
+    store.txn(&txn).await?;
+    Ok(ResignResponse {
+        header: Some(header(store)),
+    })
+}
+
+/// The oldest campaign for `name`, whose key holds the leader's value.
+fn leader(store: &MemoryStore, name: &str) -> Result<LeaderResponse> {
+    let mut range_request = RangeRequest::new_for_prefix_with_sort(
+        &format!("{}/", name),
+        SortOrder::ASCEND,
+        SortTarget::CREATE,
+    );
+    range_request.limit = Some(String::from("1"));
+    let resp = store.range_raw(&range_request)?;
+    Ok(LeaderResponse {
+        header: resp.header,
+        kv: resp.kvs.and_then(|kvs| kvs.into_iter().next()),
+    })
+}
+
+/// Stream the leader of `name` every time it changes, starting with the current one.
+async fn observe(
+    store: MemoryStore,
+    name: String,
+) -> Result<BoxStream<'static, Result<LeaderResponse>>> {
+    let changes = store.watch_pfx(&format!("{}/", name)).await?;
+    let state = (store, name, changes, None);
+    let leaders = stream::unfold(state, |(store, name, mut changes, last)| async move {
+        loop {
+            let resp = match leader(&store, &name) {
+                Ok(resp) => resp,
+                Err(err) => return Some((Err(err), (store, name, changes, last))),
+            };
+            let current = resp.kv.as_ref().map(|kv| (kv.key(), kv.mod_rev()));
+            if current.is_some() && current != last {
+                return Some((Ok(resp), (store, name, changes, current)));
+            }
+            match changes.try_next().await {
+                Ok(Some(_)) => continue,
+                Ok(None) => return None,
+                Err(err) => return Some((Err(err), (store, name, changes, last))),
+            }
+        }
+    });
+    Ok(leaders.boxed())
+}
+
+fn parse<T: DeserializeOwned>(body: &[u8]) -> Result<T> {
+    Ok(serde_json::from_slice(body)?)
+}
+
+fn full(body: String) -> Body {
+    Full::new(Bytes::from(body)).boxed_unsync()
+}
+
+fn unary<T: Serialize>(resp: &T) -> Result<Response<Body>> {
+    Ok(Response::new(full(serde_json::to_string(resp)?)))
+}
+
+/// A response body with one JSON message per line, ending at the first error.
+fn streaming<T>(messages: impl futures::Stream<Item = Result<T>> + Send + 'static) -> Response<Body>
+where
+    T: Serialize + Send + 'static,
+{
+    let frames = messages
+        .take_while(|msg| future::ready(msg.is_ok()))
+        .filter_map(|msg| {
+            let line = msg.and_then(|msg| Ok(serde_json::to_string(&msg)?));
+            future::ready(line.ok())
+        })
+        .map(|line| Ok(Frame::data(Bytes::from(line + "\n"))));
+    Response::new(StreamBody::new(frames).boxed_unsync())
+}
+
+fn header(store: &MemoryStore) -> ResponseHeader {
+    etcd_memory::header(store.revision())
+}
+
+fn number(field: &Option<String>) -> i64 {
+    field.as_ref().map_or(0, |v| v.parse::<i64>().unwrap_or(0))
+}
+
+fn text(field: &Option<String>) -> String {
+    let bytes = field
+        .as_ref()
+        .and_then(|v| base64::decode(v).ok())
+        .unwrap_or_default();
+    String::from_utf8_lossy(&bytes).into_owned()
+}